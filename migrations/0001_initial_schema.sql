@@ -0,0 +1,38 @@
+-- Initial schema (identical to the tables that used to be created by database.sql,
+-- so that existing databases can adopt the migrations without losing any data)
+
+CREATE TABLE IF NOT EXISTS leaderboard (
+    id SERIAL PRIMARY KEY,
+    name CHAR(3) NOT NULL,
+    gender CHAR(1) NOT NULL,
+    email TEXT NOT NULL,
+    difficulty INT NOT NULL,
+    score INT NOT NULL CHECK (score >= 0 AND score <= 4060),
+    materials JSONB,
+    creation_date TIMESTAMPTZ NOT NULL DEFAULT (clock_timestamp() AT TIME ZONE 'Asia/Singapore')
+);
+
+CREATE TABLE IF NOT EXISTS material (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    value INT NOT NULL CHECK (value > 0 AND value <= 1000),
+    quantity INT NOT NULL CHECK (quantity >= 0 AND quantity <= 10000)
+);
+
+
+-- Add all valid/verified materials to the material table (unless the table has
+-- already been populated)
+INSERT INTO material (name, value, quantity)
+    SELECT name, value, quantity FROM (
+        VALUES  ('jigsawAcrylic', 20, 0),
+                ('jigsawMetal', 30, 0),
+                ('jigsawWood', 20, 0),
+                ('drilledAcrylic', 50, 0),
+                ('drilledMetal', 60, 0),
+                ('drilledWood', 50, 0),
+                ('acrylicStrips', 55, 0),
+                ('woodStrips', 55, 0),
+                ('threeDPrint', 25, 0),
+                ('solderedPcb', 40, 0)
+    ) AS seed(name, value, quantity)
+    WHERE NOT EXISTS (SELECT 1 FROM material);
@@ -0,0 +1,7 @@
+-- Store the client-supplied idempotency key (if any) of each score submission so
+-- that retried submissions can be detected
+
+ALTER TABLE leaderboard ADD COLUMN IF NOT EXISTS idempotency_key TEXT;
+
+CREATE INDEX IF NOT EXISTS leaderboard_idempotency_key_idx ON leaderboard (idempotency_key)
+    WHERE idempotency_key IS NOT NULL;
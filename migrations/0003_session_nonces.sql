@@ -0,0 +1,9 @@
+-- Remember the nonces of the session tokens which have already been used to
+-- submit a score (until the tokens expire) so that they cannot be replayed
+
+CREATE TABLE IF NOT EXISTS session_nonce (
+    nonce TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS session_nonce_expires_at_idx ON session_nonce (expires_at);
@@ -0,0 +1,5 @@
+-- Record the verdict of the plausibility model for each score submission (entries
+-- submitted before the model existed are left unchecked)
+
+ALTER TABLE leaderboard ADD COLUMN IF NOT EXISTS verdict TEXT NOT NULL DEFAULT 'unchecked'
+    CHECK (verdict IN ('unchecked', 'plausible', 'implausible'));
@@ -0,0 +1,10 @@
+-- Track the moderation status of each leaderboard entry (together with the
+-- reason of the last status change) so that entries can be reviewed before
+-- giving out prizes, and flag the entries already judged to be implausible
+
+ALTER TABLE leaderboard ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'approved', 'flagged', 'removed'));
+ALTER TABLE leaderboard ADD COLUMN IF NOT EXISTS status_reason TEXT;
+
+UPDATE leaderboard SET status = 'flagged', status_reason = 'implausible score for the session duration'
+    WHERE verdict = 'implausible' AND status = 'pending';
@@ -0,0 +1,17 @@
+-- Define the community goals (such as the 3 project prototypes, 1 for each
+-- difficulty) which are unlocked once all players together have collected the
+-- required quantity of each of their materials
+
+CREATE TABLE IF NOT EXISTS goal (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    difficulty INT NOT NULL,
+    unlocked_at TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS goal_requirement (
+    goal_id INT NOT NULL REFERENCES goal (id) ON DELETE CASCADE,
+    material_id INT NOT NULL REFERENCES material (id) ON DELETE CASCADE,
+    quantity INT NOT NULL CHECK (quantity > 0),
+    PRIMARY KEY (goal_id, material_id)
+);
@@ -0,0 +1,6 @@
+-- Record the deliberate changes of the material quantities which are not backed
+-- by any leaderboard entry (such as imported totals or resets by admins), so
+-- that the quantity of each material always equals the sum of the materials of
+-- all leaderboard entries plus its adjustment
+
+ALTER TABLE material ADD COLUMN IF NOT EXISTS adjustment INT NOT NULL DEFAULT 0;
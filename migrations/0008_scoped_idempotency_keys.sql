@@ -0,0 +1,10 @@
+-- Scope the idempotency keys to the email of the player and store a digest of
+-- each submission, so that a key reused by another player (or for a different
+-- entry) is never answered with the original entry
+
+ALTER TABLE leaderboard ADD COLUMN IF NOT EXISTS idempotency_hash TEXT;
+
+DROP INDEX IF EXISTS leaderboard_idempotency_key_idx;
+
+CREATE INDEX IF NOT EXISTS leaderboard_email_idempotency_key_idx
+    ON leaderboard (lower(email), idempotency_key) WHERE idempotency_key IS NOT NULL;
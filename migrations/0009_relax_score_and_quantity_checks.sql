@@ -0,0 +1,9 @@
+-- Leave the upper limit of the scores to the configuration (`SCORING__MAX_SCORE`)
+-- instead of failing the insertion of every score above the original limit, and
+-- let the material totals grow beyond the original limit during the event
+
+ALTER TABLE leaderboard DROP CONSTRAINT IF EXISTS leaderboard_score_check;
+ALTER TABLE leaderboard ADD CONSTRAINT leaderboard_score_check CHECK (score >= 0);
+
+ALTER TABLE material DROP CONSTRAINT IF EXISTS material_quantity_check;
+ALTER TABLE material ADD CONSTRAINT material_quantity_check CHECK (quantity >= 0);
@@ -0,0 +1,90 @@
+// Define the authentication of the admin API. Admins send a bearer token in the
+// `Authorization` header, and only the SHA-256 digests of the valid tokens are
+// configured (see `AdminConfig`), so that the tokens themselves are never
+// stored on the server.
+use actix_web::{http::header, HttpRequest};
+use openssl::{memcmp, sha::sha256};
+
+use crate::{config::AdminConfig, errors::CustomError, utils::to_hex};
+
+pub fn hash_token(token: &str) -> String {
+    to_hex(&sha256(token.as_bytes()))
+}
+
+// Reject the request as forbidden unless it carries one of the configured admin
+// tokens.
+pub fn check_if_admin_authorized(
+    req: &HttpRequest,
+    config: &AdminConfig,
+) -> Result<(), CustomError> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(CustomError::Forbidden)?;
+
+    let digest = hash_token(token.trim());
+
+    // Compare in constant time to avoid leaking the configured digests.
+    if config
+        .token_hashes
+        .iter()
+        .any(|hash| hash.len() == digest.len() && memcmp::eq(hash.as_bytes(), digest.as_bytes()))
+    {
+        Ok(())
+    } else {
+        Err(CustomError::Forbidden)
+    }
+}
+
+// Define unit tests for the admin authentication.
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::{check_if_admin_authorized, hash_token};
+    use crate::{config::AdminConfig, errors::CustomError};
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(
+            hash_token("admin"),
+            "8c6976e5b5410415bde908bd4dee15dfb167a9c873fc4bb8a81f6f2ab448a918".to_string()
+        );
+    }
+
+    #[test]
+    fn test_check_if_admin_authorized() {
+        let config = AdminConfig {
+            token_hashes: vec![hash_token("correct-admin-token")],
+        };
+
+        let req = TestRequest::default()
+            .header("Authorization", "Bearer correct-admin-token")
+            .to_http_request();
+        assert!(check_if_admin_authorized(&req, &config).is_ok());
+
+        for authorization in [
+            None,
+            Some("Bearer wrong-admin-token"),
+            Some("correct-admin-token"),
+        ] {
+            let req = match authorization {
+                Some(value) => TestRequest::default().header("Authorization", value),
+                None => TestRequest::default(),
+            }
+            .to_http_request();
+
+            match check_if_admin_authorized(&req, &config) {
+                Err(CustomError::Forbidden) => (),
+                _ => panic!("requests without a valid admin token should be forbidden"),
+            }
+        }
+
+        let req = TestRequest::default()
+            .header("Authorization", "Bearer correct-admin-token")
+            .to_http_request();
+        assert!(check_if_admin_authorized(&req, &AdminConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,479 @@
+// Define the command-line interface of the backend server binary. Running the
+// binary without a subcommand starts the server (just like `serve`), so that
+// existing deployments keep working as they are.
+use clap::{Parser, Subcommand};
+use deadpool_postgres::Pool;
+use serde_json::json;
+use slog::{info, warn, Logger};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+};
+
+use crate::{
+    config::Config,
+    constants, db,
+    errors::{CustomError, StartupError},
+    migrations,
+    models::{
+        ExportFormat, GoalDefinition, LeaderboardExportEntry, MaterialEntry, MaterialValueEntry,
+    },
+    utils,
+};
+
+#[derive(Parser)]
+#[clap(version, about = "Backend database server of the Overdue game")]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[clap(about = "Start the server (default)")]
+    Serve,
+    #[clap(flatten)]
+    Task(Task),
+}
+
+// Subcommands which run a one-off task against the database instead of
+// starting the server.
+#[derive(Subcommand)]
+pub enum Task {
+    #[clap(about = "Apply all pending database migrations")]
+    Migrate,
+    #[clap(about = "Insert or update material values from a JSON file")]
+    SeedMaterials {
+        #[clap(help = "JSON array of `{\"name\": ..., \"value\": ...}` objects")]
+        file: String,
+    },
+    #[clap(about = "Insert or update community goals from a JSON file")]
+    SeedGoals {
+        #[clap(
+            help = "JSON array of `{\"name\": ..., \"difficulty\": ..., \"materials\": [{\"name\": ..., \"quantity\": ...}]}` objects"
+        )]
+        file: String,
+    },
+    #[clap(about = "Export the leaderboard entries (including emails)")]
+    ExportLeaderboard {
+        #[clap(long, possible_values = ["easy", "normal", "hard"], help = "Only export entries of this difficulty")]
+        difficulty: Option<String>,
+        #[clap(long, arg_enum, default_value = "csv")]
+        format: ExportFormat,
+        #[clap(
+            short,
+            long,
+            help = "Write to this file instead of the standard output"
+        )]
+        output: Option<String>,
+    },
+    #[clap(about = "Report the prize winners (the best unique emails) of each difficulty")]
+    PrizeWinners {
+        #[clap(long, default_value = "3", help = "Number of winners per difficulty")]
+        count: i64,
+        #[clap(long, arg_enum, default_value = "csv")]
+        format: ExportFormat,
+        #[clap(
+            short,
+            long,
+            help = "Write to this file instead of the standard output"
+        )]
+        output: Option<String>,
+    },
+    #[clap(about = "Compare the material quantities with the totals of the leaderboard entries")]
+    ReconcileMaterials {
+        #[clap(
+            long,
+            help = "Overwrite the material quantities with the recomputed totals"
+        )]
+        repair: bool,
+    },
+    #[clap(about = "Sum up material quantities from a file of submitted materials arrays")]
+    AggregateMaterials {
+        #[clap(
+            default_value = "input_materials.json",
+            help = "File with one JSON materials array per line"
+        )]
+        input: String,
+        #[clap(
+            long,
+            help = "Add the totals to the material quantities in the database"
+        )]
+        apply: bool,
+    },
+}
+
+// Load the configuration and create the database pool, like the server does.
+fn connect() -> Result<Pool, StartupError> {
+    Config::from_env()?.configure_pool()
+}
+
+fn open_file(path: &str) -> Result<File, StartupError> {
+    File::open(path).map_err(|source| StartupError::File {
+        path: path.to_string(),
+        source,
+    })
+}
+
+// Run a one-off task.
+pub async fn run(task: Task, log: &Logger) -> Result<(), StartupError> {
+    match task {
+        Task::Migrate => {
+            let applied = migrations::run_migrations(&connect()?, log).await?;
+            info!(log, "Applied {} pending migration(s)", applied.len());
+        }
+        Task::SeedMaterials { file } => seed_materials(&file, log).await?,
+        Task::SeedGoals { file } => seed_goals(&file, log).await?,
+        Task::ExportLeaderboard {
+            difficulty,
+            format,
+            output,
+        } => export_leaderboard(difficulty, format, output, log).await?,
+        Task::PrizeWinners {
+            count,
+            format,
+            output,
+        } => prize_winners(count, format, output, log).await?,
+        Task::AggregateMaterials { input, apply } => {
+            aggregate_materials(&input, apply, log).await?
+        }
+        Task::ReconcileMaterials { repair } => reconcile_materials(repair, log).await?,
+    }
+
+    Ok(())
+}
+
+async fn seed_materials(path: &str, log: &Logger) -> Result<(), StartupError> {
+    let materials: Vec<MaterialValueEntry> = serde_json::from_reader(open_file(path)?)?;
+
+    let mut already_seen = vec![];
+    for material in materials.iter() {
+        if material.name.is_empty() || material.value <= 0 {
+            return Err(StartupError::InvalidInput(format!(
+                "material `{}` must have a non-empty name and a positive value",
+                material.name
+            )));
+        }
+        if already_seen.contains(&&material.name) {
+            return Err(StartupError::InvalidInput(format!(
+                "material `{}` is listed more than once",
+                material.name
+            )));
+        }
+        already_seen.push(&material.name);
+    }
+
+    let client = connect()?.get().await?;
+    let count = db::upsert_material_values(&client, &materials).await?;
+
+    info!(log, "Seeded {} material(s)", count; "file" => path);
+
+    Ok(())
+}
+
+async fn seed_goals(path: &str, log: &Logger) -> Result<(), StartupError> {
+    let goals: Vec<GoalDefinition> = serde_json::from_reader(open_file(path)?)?;
+
+    let mut already_seen = vec![];
+    for goal in goals.iter() {
+        if goal.name.is_empty() || !constants::DIFFICULTY_MAP.contains_key(goal.difficulty.as_str())
+        {
+            return Err(StartupError::InvalidInput(format!(
+                "goal `{}` must have a non-empty name and a valid difficulty",
+                goal.name
+            )));
+        }
+        if goal.materials.is_empty() || goal.materials.iter().any(|material| material.quantity <= 0)
+        {
+            return Err(StartupError::InvalidInput(format!(
+                "goal `{}` must require a positive quantity of at least one material",
+                goal.name
+            )));
+        }
+        if already_seen.contains(&&goal.name) {
+            return Err(StartupError::InvalidInput(format!(
+                "goal `{}` is listed more than once",
+                goal.name
+            )));
+        }
+        already_seen.push(&goal.name);
+    }
+
+    let mut client = connect()?.get().await?;
+
+    // Unknown (or repeated) materials would otherwise be rejected without a
+    // helpful message.
+    let values = utils::get_values_of_materials(&client).await?;
+    for goal in goals.iter() {
+        for (index, material) in goal.materials.iter().enumerate() {
+            if !values.iter().any(|value| value.name == material.name)
+                || goal.materials[..index]
+                    .iter()
+                    .any(|other| other.name == material.name)
+            {
+                return Err(StartupError::InvalidInput(format!(
+                    "material `{}` of goal `{}` does not exist in the database or is listed more \
+                     than once",
+                    material.name, goal.name
+                )));
+            }
+        }
+    }
+
+    let count = db::upsert_goals(&mut client, &goals).await?;
+
+    info!(log, "Seeded {} goal(s)", count; "file" => path);
+
+    Ok(())
+}
+
+async fn export_leaderboard(
+    difficulty: Option<String>,
+    format: ExportFormat,
+    output: Option<String>,
+    log: &Logger,
+) -> Result<(), StartupError> {
+    let difficulty = difficulty.map(|difficulty| {
+        *constants::DIFFICULTY_MAP
+            .get::<str>(&difficulty)
+            .expect("error mapping difficulty string to integer")
+    });
+
+    let client = connect()?.get().await?;
+    let entries = db::get_leaderboard_export(&client, difficulty).await?;
+
+    match format {
+        ExportFormat::Csv => write_leaderboard_csv(create_output(&output)?, &entries)?,
+        ExportFormat::Json => serde_json::to_writer_pretty(create_output(&output)?, &entries)?,
+    }
+
+    info!(log, "Exported {} leaderboard entries", entries.len());
+
+    Ok(())
+}
+
+fn create_output(output: &Option<String>) -> Result<Box<dyn Write>, StartupError> {
+    Ok(match output {
+        Some(path) => Box::new(File::create(path).map_err(|source| StartupError::File {
+            path: path.clone(),
+            source,
+        })?),
+        None => Box::new(io::stdout()),
+    })
+}
+
+async fn prize_winners(
+    count: i64,
+    format: ExportFormat,
+    output: Option<String>,
+    log: &Logger,
+) -> Result<(), StartupError> {
+    if count < 1 {
+        return Err(StartupError::InvalidInput(
+            "the number of winners must be positive".to_string(),
+        ));
+    }
+
+    let client = connect()?.get().await?;
+    let winners = db::get_prize_winners(&client, count).await?;
+
+    match format {
+        ExportFormat::Csv => utils::write_csv(create_output(&output)?, &winners)?,
+        ExportFormat::Json => serde_json::to_writer_pretty(create_output(&output)?, &winners)?,
+    }
+
+    info!(log, "Reported {} prize winners", winners.len());
+
+    Ok(())
+}
+
+fn write_leaderboard_csv(
+    writer: impl Write,
+    entries: &[LeaderboardExportEntry],
+) -> Result<(), StartupError> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    writer.write_record([
+        "id",
+        "name",
+        "gender",
+        "email",
+        "difficulty",
+        "score",
+        "materials",
+        "creation_date",
+        "verdict",
+        "status",
+        "status_reason",
+    ])?;
+
+    for entry in entries.iter() {
+        writer.write_record([
+            entry.id.to_string(),
+            entry.name.clone(),
+            entry.gender.clone(),
+            entry.email.clone(),
+            entry.difficulty.clone(),
+            entry.score.to_string(),
+            serde_json::to_string(&entry.materials)?,
+            entry.creation_date.to_rfc3339(),
+            entry.verdict.clone(),
+            entry.status.clone(),
+            entry.status_reason.clone().unwrap_or_default(),
+        ])?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Sum up the quantity of each material over all lines (keeping the order in
+// which the materials first appear) and return the totals together with the
+// overall number of materials.
+pub fn sum_materials(reader: impl BufRead) -> Result<(Vec<MaterialEntry>, i64), StartupError> {
+    let mut totals: Vec<MaterialEntry> = vec![];
+    let mut total_number_of_materials: i64 = 0;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let materials: Vec<MaterialEntry> = serde_json::from_str(&line).map_err(|err| {
+            StartupError::InvalidInput(format!(
+                "line {} is not a materials array: {}",
+                index + 1,
+                err
+            ))
+        })?;
+
+        for material in materials {
+            total_number_of_materials += i64::from(material.quantity);
+
+            match totals.iter_mut().find(|total| total.name == material.name) {
+                Some(total) => total.quantity += material.quantity,
+                None => totals.push(material),
+            }
+        }
+    }
+
+    Ok((totals, total_number_of_materials))
+}
+
+async fn aggregate_materials(path: &str, apply: bool, log: &Logger) -> Result<(), StartupError> {
+    let (totals, total_number_of_materials) = sum_materials(BufReader::new(open_file(path)?))?;
+
+    // The report is the output of the command (just like the exports), so it
+    // goes to the standard output while the log records go to the log drain.
+    let mut stdout = io::stdout().lock();
+    writeln!(
+        stdout,
+        "MATERIALS: {}",
+        json!(totals
+            .iter()
+            .map(|total| (total.name.clone(), json!(total.quantity)))
+            .collect::<serde_json::Map<String, serde_json::Value>>())
+    )?;
+    writeln!(stdout, "TOTAL CONTRIBUTION: {}", total_number_of_materials)?;
+    drop(stdout);
+
+    if !apply || totals.is_empty() {
+        return Ok(());
+    }
+
+    let mut client = connect()?.get().await?;
+
+    // Unknown materials would otherwise be silently skipped by the update.
+    let values = utils::get_values_of_materials(&client).await?;
+    if let Some(unknown) = totals
+        .iter()
+        .find(|total| !values.iter().any(|value| value.name == total.name))
+    {
+        return Err(StartupError::InvalidInput(format!(
+            "material `{}` does not exist in the database",
+            unknown.name
+        )));
+    }
+
+    let transaction = client.transaction().await?;
+    if !utils::add_materials_to_aggregate(&transaction, totals.clone()).await? {
+        return Err(CustomError::DbError.into());
+    }
+    db::add_material_adjustments(&transaction, &totals).await?;
+    db::unlock_goals(&transaction).await?;
+    transaction.commit().await?;
+
+    info!(log, "Added the material totals to the database"; "file" => path);
+
+    Ok(())
+}
+
+async fn reconcile_materials(repair: bool, log: &Logger) -> Result<(), StartupError> {
+    let mut client = connect()?.get().await?;
+    let drift = db::reconcile_materials(&mut client, repair).await?;
+
+    // Like the aggregation report, the drift report goes to the standard output.
+    let mut stdout = io::stdout().lock();
+    for material in drift.iter() {
+        writeln!(
+            stdout,
+            "{}: recorded {}, expected {} ({:+})",
+            material.name,
+            material.recorded,
+            material.expected,
+            i64::from(material.recorded) - material.expected
+        )?;
+    }
+    drop(stdout);
+
+    match (drift.is_empty(), repair) {
+        (true, _) => info!(
+            log,
+            "Material quantities are consistent with the leaderboard"
+        ),
+        (false, true) => info!(log, "Repaired {} drifted material quantities", drift.len()),
+        (false, false) => warn!(
+            log,
+            "Found {} drifted material quantities (run with `--repair` to fix them)",
+            drift.len()
+        ),
+    }
+
+    Ok(())
+}
+
+// Define unit tests for the material aggregation.
+#[cfg(test)]
+mod tests {
+    use super::sum_materials;
+    use crate::errors::StartupError;
+
+    #[test]
+    fn test_sum_materials_over_lines() {
+        let input = "[{\"name\": \"jigsawWood\", \"quantity\": 2}, {\"name\": \"woodStrips\", \
+                     \"quantity\": 1}]\n\n[{\"name\": \"woodStrips\", \"quantity\": 3}]\n[]\n";
+
+        let (totals, total_number_of_materials) =
+            sum_materials(input.as_bytes()).expect("error summing materials");
+
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[0].name, "jigsawWood".to_string());
+        assert_eq!(totals[0].quantity, 2);
+        assert_eq!(totals[1].name, "woodStrips".to_string());
+        assert_eq!(totals[1].quantity, 4);
+        assert_eq!(total_number_of_materials, 6);
+    }
+
+    #[test]
+    fn test_sum_materials_with_malformed_line() {
+        let input = "[{\"name\": \"jigsawWood\", \"quantity\": 2}]\n{\"name\": \"woodStrips\"}\n";
+
+        match sum_materials(input.as_bytes()) {
+            Err(StartupError::InvalidInput(message)) => assert!(message.starts_with("line 2")),
+            _ => panic!("malformed line should return an invalid input error"),
+        }
+    }
+}
@@ -1,34 +1,561 @@
-use config::ConfigError;
-use deadpool_postgres::Pool;
-use serde::Deserialize;
-use tokio_postgres::NoTls;
+use deadpool_postgres::{config::SslMode, Pool};
+use openssl::ssl::SslVersion;
+use serde::{Deserialize, Deserializer};
+use std::net::Ipv6Addr;
+
+use crate::{db, defaults, errors::StartupError};
+
+// Environment variables can only hold strings, so lists are given as
+// comma-separated values.
+fn deserialize_comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+
+    Ok(value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect())
+}
 
 #[derive(Deserialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u32,
+    // Host names that requests may be addressed to (via the `Host` header). See
+    // `guards::AllowedHosts` for the supported patterns.
+    #[serde(
+        default = "defaults::default_allowed_hosts",
+        deserialize_with = "deserialize_comma_separated"
+    )]
+    pub allowed_hosts: Vec<String>,
+    #[serde(default = "defaults::default_keep_alive")]
+    pub keep_alive: usize,
+    // Maximum size (in bytes) of the JSON payload of a score submission.
+    #[serde(default = "defaults::default_score_payload_limit")]
+    pub score_payload_limit: usize,
+    // Port of the optional plain-HTTP listener which only redirects to HTTPS and
+    // serves ACME HTTP-01 challenge files.
+    pub http_port: Option<u32>,
+    // Directory containing the `.well-known/acme-challenge` tokens (e.g., the
+    // Certbot webroot's challenge directory).
+    pub acme_challenge_dir: Option<String>,
+    // Maximum time (in seconds) to wait for in-flight requests to finish after a
+    // shutdown signal has been received.
+    #[serde(default = "defaults::default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    // Apply pending database migrations before starting the server.
+    #[serde(default)]
+    pub auto_migrate: bool,
+}
+
+// Minimum TLS protocol versions that can be accepted by the server.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TlsVersion {
+    #[serde(rename = "tlsv1")]
+    Tls1,
+    #[serde(rename = "tlsv1.1")]
+    Tls1_1,
+    #[serde(rename = "tlsv1.2")]
+    Tls1_2,
+    #[serde(rename = "tlsv1.3")]
+    Tls1_3,
+}
+
+impl From<TlsVersion> for SslVersion {
+    fn from(version: TlsVersion) -> Self {
+        match version {
+            TlsVersion::Tls1 => SslVersion::TLS1,
+            TlsVersion::Tls1_1 => SslVersion::TLS1_1,
+            TlsVersion::Tls1_2 => SslVersion::TLS1_2,
+            TlsVersion::Tls1_3 => SslVersion::TLS1_3,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TlsConfig {
+    // Path to the PEM-encoded certificate chain (leaf certificate first).
+    #[serde(default = "defaults::default_tls_cert_chain")]
+    pub cert_chain: String,
+    // Path to the PEM-encoded private key of the leaf certificate.
+    #[serde(default = "defaults::default_tls_key")]
+    pub key: String,
+    // Path to the PEM-encoded CA bundle used to verify client certificates. Client
+    // certificates are only requested if this is set.
+    pub client_ca: Option<String>,
+    #[serde(default = "defaults::default_tls_min_version")]
+    pub min_version: TlsVersion,
+    // OpenSSL cipher list string (only applies to TLSv1.2 and below).
+    pub ciphers: Option<String>,
+    // Interval (in seconds) at which the certificate files are checked for
+    // changes. Set to 0 to only reload on SIGHUP.
+    #[serde(default = "defaults::default_tls_reload_interval")]
+    pub reload_interval: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_chain: defaults::default_tls_cert_chain(),
+            key: defaults::default_tls_key(),
+            client_ca: None,
+            min_version: defaults::default_tls_min_version(),
+            ciphers: None,
+            reload_interval: defaults::default_tls_reload_interval(),
+        }
+    }
+}
+
+// TLS modes of the database connection, following the semantics of libpq's
+// `sslmode` parameter.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PgSslMode {
+    // Do not use TLS.
+    Disable,
+    // Use TLS if the server supports it, without verifying its certificate.
+    Prefer,
+    // Require TLS, without verifying the server certificate (unless a root
+    // certificate is given, in which case it behaves like `verify-ca`).
+    Require,
+    // Require TLS and verify that the server certificate is signed by a trusted
+    // CA.
+    VerifyCa,
+    // Like `verify-ca`, but also verify that the server host name matches the
+    // certificate.
+    VerifyFull,
+}
+
+impl From<PgSslMode> for SslMode {
+    fn from(mode: PgSslMode) -> Self {
+        match mode {
+            PgSslMode::Disable => SslMode::Disable,
+            PgSslMode::Prefer => SslMode::Prefer,
+            PgSslMode::Require | PgSslMode::VerifyCa | PgSslMode::VerifyFull => SslMode::Require,
+        }
+    }
+}
+
+// These keys live next to the connection settings in the `PG__` namespace (like
+// libpq's `sslmode` and `sslrootcert`) but are not understood by deadpool.
+#[derive(Deserialize, Clone)]
+pub struct PgTlsConfig {
+    #[serde(default = "defaults::default_pg_sslmode")]
+    pub sslmode: PgSslMode,
+    // Path to the PEM-encoded CA certificate that signed the database server
+    // certificate.
+    pub sslrootcert: Option<String>,
+}
+
+impl Default for PgTlsConfig {
+    fn default() -> Self {
+        Self {
+            sslmode: defaults::default_pg_sslmode(),
+            sslrootcert: None,
+        }
+    }
+}
+
+// Limits used to validate score submissions.
+#[derive(Deserialize, Clone)]
+pub struct ScoringConfig {
+    #[serde(default = "defaults::default_max_score")]
+    pub max_score: i32,
+    #[serde(default = "defaults::default_min_score")]
+    pub min_score: i32,
+    // Limits of the quantity of each material in a single submission.
+    #[serde(default = "defaults::default_max_materials")]
+    pub max_materials: i32,
+    #[serde(default = "defaults::default_min_materials")]
+    pub min_materials: i32,
+    #[serde(default = "defaults::default_max_bonus_value")]
+    pub max_bonus_value: i32,
+    #[serde(default = "defaults::default_min_bonus_value")]
+    pub min_bonus_value: i32,
+    // Length (in seconds) of the window in which a submission with an already
+    // used idempotency key returns the original entry instead of adding a new
+    // one.
+    #[serde(default = "defaults::default_idempotency_window")]
+    pub idempotency_window: u64,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            max_score: defaults::default_max_score(),
+            min_score: defaults::default_min_score(),
+            max_materials: defaults::default_max_materials(),
+            min_materials: defaults::default_min_materials(),
+            max_bonus_value: defaults::default_max_bonus_value(),
+            min_bonus_value: defaults::default_min_bonus_value(),
+            idempotency_window: defaults::default_idempotency_window(),
+        }
+    }
+}
+
+// Settings of the game-session tokens issued by `/start_session`.
+#[derive(Deserialize, Clone)]
+pub struct SessionConfig {
+    // Secret key used to sign the session tokens (keep it private and at least 32
+    // bytes long). Only the server needs it (see `Config::validate_serve`), so
+    // that the maintenance subcommands also work without it.
+    #[serde(default)]
+    pub secret: Option<String>,
+    // Time (in seconds) after which a session token can no longer be used to
+    // submit a score.
+    #[serde(default = "defaults::default_session_lifetime")]
+    pub lifetime: u64,
+    // Shortest time (in seconds) in which a game can possibly be played. Scores
+    // from shorter sessions are always rejected, whatever the plausibility
+    // action is.
+    #[serde(default = "defaults::default_session_min_duration")]
+    pub min_duration: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            lifetime: defaults::default_session_lifetime(),
+            min_duration: defaults::default_session_min_duration(),
+        }
+    }
+}
+
+// What to do with submissions that are judged to be implausible.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlausibilityAction {
+    // Accept the entry, but record the verdict for manual review.
+    Flag,
+    // Reject the submission.
+    Reject,
+}
+
+// Plausibility limits of a single difficulty, relative to the duration of the
+// play session.
+#[derive(Deserialize, Clone)]
+pub struct DifficultyLimits {
+    #[serde(default = "defaults::default_plausible_score_per_second")]
+    pub max_score_per_second: f64,
+    // Limit of the total quantity of all materials collected per minute.
+    #[serde(default = "defaults::default_plausible_materials_per_minute")]
+    pub max_materials_per_minute: f64,
+}
+
+impl Default for DifficultyLimits {
+    fn default() -> Self {
+        Self {
+            max_score_per_second: defaults::default_plausible_score_per_second(),
+            max_materials_per_minute: defaults::default_plausible_materials_per_minute(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PlausibilityConfig {
+    #[serde(default = "defaults::default_plausibility_action")]
+    pub action: PlausibilityAction,
+    #[serde(default)]
+    pub easy: DifficultyLimits,
+    #[serde(default)]
+    pub normal: DifficultyLimits,
+    #[serde(default)]
+    pub hard: DifficultyLimits,
+}
+
+impl PlausibilityConfig {
+    pub fn limits(&self, difficulty: &str) -> &DifficultyLimits {
+        match difficulty {
+            "easy" => &self.easy,
+            "hard" => &self.hard,
+            _ => &self.normal,
+        }
+    }
+}
+
+impl Default for PlausibilityConfig {
+    fn default() -> Self {
+        Self {
+            action: defaults::default_plausibility_action(),
+            easy: DifficultyLimits::default(),
+            normal: DifficultyLimits::default(),
+            hard: DifficultyLimits::default(),
+        }
+    }
+}
+
+// Algorithms used to rank the entries of a leaderboard (e.g., for the scores
+// 100, 90, 90 and 80).
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RankingMode {
+    // Dense ranking (1, 2, 2, 3).
+    Dense,
+    // Standard competition ranking (1, 2, 2, 4).
+    Standard,
+    // Modified competition ranking (1, 3, 3, 4).
+    Modified,
+    // Ordinal ranking, where ties are broken by the earliest submission (1, 2,
+    // 3, 4).
+    Ordinal,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LeaderboardConfig {
+    // Limits of the `max_entries` query parameter.
+    #[serde(default = "defaults::default_max_leaderboard_length")]
+    pub max_length: i64,
+    #[serde(default = "defaults::default_min_leaderboard_length")]
+    pub min_length: i64,
+    // Hide flagged entries (and not only removed ones) from the public leaderboard
+    // until they have been reviewed.
+    #[serde(default)]
+    pub hide_flagged: bool,
+    #[serde(default = "defaults::default_ranking_mode")]
+    pub ranking: RankingMode,
+    // Only rank the best entry of each player (identified by email) by default
+    // (see the `best_per_player` query parameter).
+    #[serde(default)]
+    pub best_per_player: bool,
+    // IANA time zone (as known to PostgreSQL) in which the days and weeks of the
+    // `period` query parameter start.
+    #[serde(default = "defaults::default_leaderboard_time_zone")]
+    pub time_zone: String,
+    // Number of top entries pushed to the WebSocket clients of `/leaderboard/live`.
+    #[serde(default = "defaults::default_live_leaderboard_length")]
+    pub live_length: i64,
+}
+
+impl Default for LeaderboardConfig {
+    fn default() -> Self {
+        Self {
+            max_length: defaults::default_max_leaderboard_length(),
+            min_length: defaults::default_min_leaderboard_length(),
+            hide_flagged: false,
+            ranking: defaults::default_ranking_mode(),
+            best_per_player: false,
+            time_zone: defaults::default_leaderboard_time_zone(),
+            live_length: defaults::default_live_leaderboard_length(),
+        }
+    }
+}
+
+// Settings of the admin API (see `auth`).
+#[derive(Deserialize, Clone, Default)]
+pub struct AdminConfig {
+    // Hexadecimal SHA-256 digests of the bearer tokens that are allowed to use
+    // the admin API. The admin API cannot be used at all if this is empty.
+    #[serde(default, deserialize_with = "deserialize_comma_separated")]
+    pub token_hashes: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RateLimitConfig {
+    // Length (in seconds) of the window in which requests are counted.
+    #[serde(default = "defaults::default_rate_limit_interval")]
+    pub interval: u64,
+    // Maximum number of requests per client (based on IP address) per window.
+    #[serde(default = "defaults::default_rate_limit_max_requests")]
+    pub max_requests: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            interval: defaults::default_rate_limit_interval(),
+            max_requests: defaults::default_rate_limit_max_requests(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CorsConfig {
+    #[serde(
+        default = "defaults::default_cors_allowed_origins",
+        deserialize_with = "deserialize_comma_separated"
+    )]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "defaults::default_cors_max_age")]
+    pub max_age: usize,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: defaults::default_cors_allowed_origins(),
+            max_age: defaults::default_cors_max_age(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
     pub pg: deadpool_postgres::Config,
+    // Read separately from the `pg` table (see `Config::from_env`).
+    #[serde(skip)]
+    pub pg_tls: PgTlsConfig,
+    #[serde(default)]
+    pub scoring: ScoringConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub plausibility: PlausibilityConfig,
+    #[serde(default)]
+    pub leaderboard: LeaderboardConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub cors: CorsConfig,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, ConfigError> {
-        config::Config::builder()
+    pub fn from_env() -> Result<Self, StartupError> {
+        let source = config::Config::builder()
         .add_source(
             config::Environment::default()
             .separator("__")
         )
-        .build()?
-        .try_deserialize()
+        .build()?;
+
+        let mut config: Self = source.clone().try_deserialize()?;
+        config.pg_tls = source.get("pg")?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // Check the settings which are only needed to serve requests (and not by the
+    // maintenance subcommands).
+    pub fn validate_serve(&self) -> Result<(), StartupError> {
+        if self.session.secret.is_none() {
+            return Err(StartupError::InvalidConfig(
+                "SESSION__SECRET must be set to start the server".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Reject configurations that would make every request fail (or pass) instead
+    // of finding out about them while the event is running.
+    pub fn validate(&self) -> Result<(), StartupError> {
+        let invalid = |message: &str| Err(StartupError::InvalidConfig(message.to_string()));
+
+        let scoring = &self.scoring;
+        if scoring.min_score < 0 || scoring.min_score > scoring.max_score {
+            return invalid("SCORING__MIN_SCORE must be between 0 and SCORING__MAX_SCORE");
+        }
+        if scoring.min_materials < 0 || scoring.min_materials > scoring.max_materials {
+            return invalid("SCORING__MIN_MATERIALS must be between 0 and SCORING__MAX_MATERIALS");
+        }
+        if scoring.min_bonus_value < 0 || scoring.min_bonus_value > scoring.max_bonus_value {
+            return invalid(
+                "SCORING__MIN_BONUS_VALUE must be between 0 and SCORING__MAX_BONUS_VALUE",
+            );
+        }
+
+        let session = &self.session;
+        if session.secret.as_ref().is_some_and(|secret| secret.len() < 32) {
+            return invalid("SESSION__SECRET must be at least 32 bytes long");
+        }
+        if session.lifetime == 0 {
+            return invalid("SESSION__LIFETIME must be positive");
+        }
+        if session.min_duration >= session.lifetime {
+            return invalid("SESSION__MIN_DURATION must be less than SESSION__LIFETIME");
+        }
+
+        let plausibility = &self.plausibility;
+        if [&plausibility.easy, &plausibility.normal, &plausibility.hard]
+            .iter()
+            .any(|limits| limits.max_score_per_second <= 0.0 || limits.max_materials_per_minute <= 0.0)
+        {
+            return invalid("PLAUSIBILITY__<DIFFICULTY>__* limits must be positive");
+        }
+
+        let leaderboard = &self.leaderboard;
+        if leaderboard.min_length < 1 || leaderboard.min_length > leaderboard.max_length {
+            return invalid(
+                "LEADERBOARD__MIN_LENGTH must be between 1 and LEADERBOARD__MAX_LENGTH",
+            );
+        }
+
+        if leaderboard.live_length < leaderboard.min_length
+            || leaderboard.live_length > leaderboard.max_length
+        {
+            return invalid(
+                "LEADERBOARD__LIVE_LENGTH must be between LEADERBOARD__MIN_LENGTH and LEADERBOARD__MAX_LENGTH",
+            );
+        }
+
+        if leaderboard.time_zone.trim().is_empty() {
+            return invalid("LEADERBOARD__TIME_ZONE must not be empty");
+        }
+
+        if self.admin.token_hashes.iter().any(|hash| {
+            hash.len() != 64
+                || !hash
+                    .bytes()
+                    .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+        }) {
+            return invalid(
+                "ADMIN__TOKEN_HASHES must only contain lowercase hexadecimal SHA-256 digests",
+            );
+        }
+
+        if self.rate_limit.interval == 0 || self.rate_limit.max_requests == 0 {
+            return invalid("RATE_LIMIT__INTERVAL and RATE_LIMIT__MAX_REQUESTS must be positive");
+        }
+
+        if self.server.score_payload_limit == 0 {
+            return invalid("SERVER__SCORE_PAYLOAD_LIMIT must be positive");
+        }
+
+        if self.server.allowed_hosts.is_empty() {
+            return invalid("SERVER__ALLOWED_HOSTS must not be empty");
+        }
+        if self.server.allowed_hosts.iter().any(|host| {
+            // IPv6 literals have to be bracketed, just like in the `Host` header.
+            if let Some(address) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+                return address.parse::<Ipv6Addr>().is_err();
+            }
+
+            let name = host.strip_prefix("*.").unwrap_or(host);
+            host != "*" && (name.is_empty() || name.contains('*') || name.contains(':'))
+        }) {
+            return invalid(
+                "SERVER__ALLOWED_HOSTS must only contain host names (without ports), bracketed \
+                 IPv6 addresses, `*.` wildcard subdomains or `*`",
+            );
+        }
+
+        if self
+            .cors
+            .allowed_origins
+            .iter()
+            .any(|origin| !origin.starts_with("https://") && !origin.starts_with("http://"))
+        {
+            return invalid("CORS__ALLOWED_ORIGINS must only contain http(s):// origins");
+        }
+
+        Ok(())
     }
 
-    pub fn configure_pool(&self) -> Pool {
-        self.pg
-            .create_pool(NoTls)
-            .expect("error creating deadpool postgres database pool")
+    pub fn configure_pool(&self) -> Result<Pool, StartupError> {
+        let mut pg = self.pg.clone();
+        pg.ssl_mode = Some(self.pg_tls.sslmode.into());
+
+        Ok(pg.create_pool(db::configure_tls(&self.pg_tls)?)?)
     }
 }
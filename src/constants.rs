@@ -3,25 +3,12 @@ use regex::Regex;
 use slog::Level;
 use std::{collections::HashMap, sync::atomic::AtomicUsize};
 
-// Define non-confidential constants here.
-pub const MAX_SCORE: i32 = 4060;
-pub const MIN_SCORE: i32 = 0;
-pub const MAX_MATERIALS: i32 = 10;
-pub const MIN_MATERIALS: i32 = 0;
-pub const MAX_LEADERBOARD_LENGTH: u32 = 100;
-pub const MIN_LEADERBOARD_LENGTH: u32 = 1;
-pub const MAX_BONUS_VALUE: i32 = 10;
-pub const MIN_BONUS_VALUE: i32 = 0;
-pub const INCOMING_SCORE_PAYLOAD_LIMIT: usize = 1024;
-pub const RATE_LIMIT_INTERVAL_DURATION: u64 = 60;
-pub const RATE_LIMIT_MAX_REQUESTS: usize = 60;
-pub const KEEP_ALIVE_DURATION: usize = 150;
-pub const CORS_MAX_AGE_DURATION: usize = 150;
-pub const GAME_CLIENT_URL_DOMAIN_ORIGIN: &str = "https://sutd-fablab-game.netlify.app";
-pub const PUBLIC_FACING_GAME_CLIENT_URL: &str = "https://overdue.sutd.edu.sg";
-pub const FRONT_DOMAIN: &str = "https://openhouse.sutd.edu.sg";
-pub const SERVER_HOST_URL: &str = "sutdoverdue.dev";
+// Name of the optional request header which identifies retries of the same score
+// submission.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
 
+// Define non-confidential constants here (limits and other values which differ
+// between events are read from the runtime configuration instead).
 lazy_static! {
     pub static ref NAME_REGEX: Regex =
         Regex::new(r"^[a-zA-Z]{3}$").expect("error creating the name regex");
@@ -1,20 +1,201 @@
 use actix_web::web;
-use deadpool_postgres::Client;
+use chrono::{DateTime, NaiveDate, Utc};
+use deadpool_postgres::{Client, Transaction};
 use native_tls::{Certificate, TlsConnector};
 use postgres_native_tls::MakeTlsConnector;
+use std::fs;
 use tokio_pg_mapper::FromTokioPostgresRow;
-use tokio_postgres::types::{Json, Type};
+use tokio_postgres::{
+    error::SqlState,
+    types::{Json, Type},
+    Row,
+};
 
 use crate::{
+    config::{Config, LeaderboardConfig, PgSslMode, PgTlsConfig, RankingMode},
     constants,
-    errors::CustomError,
-    models::{LeaderboardMultipleEntries, LeaderboardSingleEntry, MaterialEntry, ScoreEntry},
+    errors::{CustomError, StartupError},
+    models::{
+        EntryStatus, GoalDefinition, GoalMaterialProgress, GoalProgress, LeaderboardAroundResponse,
+        LeaderboardExportEntry, LeaderboardMultipleEntries, LeaderboardPage, LeaderboardPeriod,
+        LeaderboardSingleEntry, MaterialDrift, MaterialEntry, MaterialValueEntry, PrizeWinnerEntry,
+        ScoreEntry,
+    },
+    plausibility::Verdict,
+    sessions::SessionClaims,
+    utils,
 };
 
-pub async fn add_score_entry(
-    client: &Client,
+// Build the TLS connector used by the database pool. The connector is not used
+// at all if `sslmode` is `disable`.
+pub fn configure_tls(config: &PgTlsConfig) -> Result<MakeTlsConnector, StartupError> {
+    // Like libpq, refuse to connect instead of silently skipping the
+    // verification if there is nothing to verify the server certificate against.
+    if matches!(config.sslmode, PgSslMode::VerifyCa | PgSslMode::VerifyFull)
+        && config.sslrootcert.is_none()
+    {
+        return Err(StartupError::InvalidConfig(
+            "PG__SSLROOTCERT must be set if PG__SSLMODE is `verify-ca` or `verify-full`"
+                .to_string(),
+        ));
+    }
+
+    let mut builder = TlsConnector::builder();
+
+    if let Some(path) = &config.sslrootcert {
+        let pem = fs::read(path).map_err(|source| StartupError::DbCaFile {
+            path: path.clone(),
+            source,
+        })?;
+        builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+
+    let verify_ca = match config.sslmode {
+        PgSslMode::Disable | PgSslMode::Prefer => false,
+        PgSslMode::Require => config.sslrootcert.is_some(),
+        PgSslMode::VerifyCa | PgSslMode::VerifyFull => true,
+    };
+
+    builder
+        .danger_accept_invalid_certs(!verify_ca)
+        .danger_accept_invalid_hostnames(config.sslmode != PgSslMode::VerifyFull);
+
+    Ok(MakeTlsConnector::new(builder.build()?))
+}
+
+// Add the submitted materials to the material totals and insert the score
+// entry in a single transaction, so that a failure in any of the statements
+// never leaves the material totals incremented without a leaderboard entry.
+// If the entry comes with an idempotency key that has already been used within
+// the last `idempotency_window` seconds, the original entry is returned instead
+// (with its current rank) and nothing is added. Otherwise, the nonce of the
+// session token is consumed, so that each session can only submit one score.
+pub async fn submit_score_entry(
+    client: &mut Client,
     item: web::Json<ScoreEntry>,
+    idempotency_key: Option<&str>,
+    session: &SessionClaims,
+    verdict: Verdict,
+    config: &Config,
 ) -> Result<Vec<LeaderboardSingleEntry>, CustomError> {
+    let leaderboard = &config.leaderboard;
+
+    // The transaction is rolled back if it is dropped before being committed
+    // (i.e., when returning early because of an error).
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    // Idempotency keys are scoped to the email of the player, and a retry has to
+    // send exactly the same entry as the original submission.
+    let idempotency_hash = idempotency_key.map(|_key| utils::hash_score_entry(&item));
+
+    if let (Some(key), Some(hash)) = (idempotency_key, &idempotency_hash) {
+        // Serialize concurrent retries with the same key (the lock is released
+        // when the transaction ends).
+        transaction
+            .execute(
+                "SELECT pg_advisory_xact_lock(hashtext(lower($1)), hashtext($2))",
+                &[&item.email, &key],
+            )
+            .await
+            .map_err(|_err| CustomError::DbError)?;
+
+        // Compare against the same expression as the default value of
+        // `creation_date` so that the window does not depend on the time zone of
+        // the database session.
+        let original = transaction
+            .query_opt(
+                "SELECT id, idempotency_hash FROM leaderboard WHERE lower(email) = lower($1) AND \
+                 idempotency_key = $2 AND creation_date > (clock_timestamp() AT TIME ZONE \
+                 'Asia/Singapore') - make_interval(secs => $3) ORDER BY id DESC LIMIT 1",
+                &[
+                    &item.email,
+                    &key,
+                    &(config.scoring.idempotency_window as f64),
+                ],
+            )
+            .await
+            .map_err(|_err| CustomError::DbError)?;
+
+        if let Some(original) = original {
+            if original
+                .get::<_, Option<String>>("idempotency_hash")
+                .as_ref()
+                != Some(hash)
+            {
+                return Err(CustomError::Conflict);
+            }
+
+            return get_ranked_score_entry(&transaction, original.get("id"), leaderboard).await;
+        }
+    }
+
+    let session_expires_at = session.started_at + config.session.lifetime as i64;
+    if !consume_session_nonce(&transaction, &session.nonce, session_expires_at).await? {
+        return Err(CustomError::Forbidden);
+    }
+
+    // Do not need to add any materials to aggregate if material vector is empty.
+    if !item.materials.is_empty() {
+        if !utils::add_materials_to_aggregate(&transaction, item.materials.clone()).await? {
+            return Err(CustomError::DbError);
+        }
+
+        unlock_goals(&transaction).await?;
+    }
+
+    let id = add_score_entry(
+        &transaction,
+        item,
+        idempotency_key,
+        idempotency_hash.as_deref(),
+        verdict,
+    )
+    .await?;
+    let score = get_ranked_score_entry(&transaction, id, leaderboard).await?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(score)
+}
+
+// Record the nonce as used and return whether it had not been used before. The
+// nonces of expired sessions are forgotten since their tokens are rejected
+// anyway.
+pub async fn consume_session_nonce(
+    client: &Transaction<'_>,
+    nonce: &str,
+    expires_at: i64,
+) -> Result<bool, CustomError> {
+    client
+        .execute("DELETE FROM session_nonce WHERE expires_at < now()", &[])
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let inserted = client
+        .execute(
+            "INSERT INTO session_nonce (nonce, expires_at) VALUES ($1, to_timestamp($2)) ON \
+             CONFLICT (nonce) DO NOTHING",
+            &[&nonce, &(expires_at as f64)],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(inserted == 1)
+}
+
+pub async fn add_score_entry(
+    client: &Transaction<'_>,
+    item: web::Json<ScoreEntry>,
+    idempotency_key: Option<&str>,
+    idempotency_hash: Option<&str>,
+    verdict: Verdict,
+) -> Result<i32, CustomError> {
     // Map text/string/bpchar/varchar to integer since integer-based
     // operations/comparisons are generally much faster.
     let mapped_difficulty = constants::DIFFICULTY_MAP
@@ -23,10 +204,20 @@ pub async fn add_score_entry(
 
     let lowercased_gender: String = item.gender.clone().to_ascii_lowercase();
 
+    // Implausible entries are flagged for review right away.
+    let (status, status_reason) = match verdict {
+        Verdict::Plausible => (EntryStatus::Pending, None),
+        Verdict::Implausible => (
+            EntryStatus::Flagged,
+            Some("implausible score for the session duration"),
+        ),
+    };
+
     let statement = client
         .prepare_typed(
-            "INSERT INTO leaderboard (name, gender, email, difficulty, score, materials) VALUES \
-             ($1, $2, $3, $4, $5, $6) RETURNING id, name, gender, difficulty, score",
+            "INSERT INTO leaderboard (name, gender, email, difficulty, score, materials, \
+             idempotency_key, idempotency_hash, verdict, status, status_reason) VALUES ($1, $2, $3, \
+             $4, $5, $6, $7, $8, $9, $10, $11) RETURNING id",
             &[
                 Type::BPCHAR,
                 Type::BPCHAR,
@@ -34,6 +225,11 @@ pub async fn add_score_entry(
                 Type::INT4,
                 Type::INT4,
                 Type::JSONB,
+                Type::TEXT,
+                Type::TEXT,
+                Type::TEXT,
+                Type::TEXT,
+                Type::TEXT,
             ],
         )
         .await
@@ -51,23 +247,140 @@ pub async fn add_score_entry(
                 &mapped_difficulty.clone(),
                 &item.score.clone(),
                 &Json(&item.materials.clone()),
+                &idempotency_key,
+                &idempotency_hash,
+                &verdict.as_str(),
+                &status.as_str(),
+                &status_reason,
             ],
         )
         .await
         .map_err(|_err| CustomError::DbError)?;
 
-    let id: i32 = score[0].get("id");
+    Ok(score[0].get("id"))
+}
 
-    let rank_statement = client
-        .prepare(
-            "SELECT subquery.rank FROM (SELECT id, dense_rank() OVER (PARTITION BY difficulty \
-             ORDER BY score DESC) rank FROM leaderboard) subquery WHERE subquery.id = $1",
+// Actual time of a submission. `creation_date` holds the wall-clock time in
+// Singapore (see the default value of the column) as if it were in the time
+// zone of the database session, so it is converted back before comparing it
+// with other times.
+const SUBMISSION_TIME: &str =
+    "((creation_date AT TIME ZONE current_setting('TimeZone')) AT TIME ZONE 'Asia/Singapore')";
+
+// Order of the ranked entries on the leaderboard, which agrees with their ranks
+// in every ranking mode.
+const LEADERBOARD_ORDER: &str = "score DESC, creation_date ASC, id ASC";
+
+// Window function computing the rank of each entry among the entries of its
+// difficulty.
+fn rank_expression(mode: RankingMode) -> &'static str {
+    match mode {
+        RankingMode::Dense => "dense_rank() OVER (PARTITION BY difficulty ORDER BY score DESC)",
+        RankingMode::Standard => "rank() OVER (PARTITION BY difficulty ORDER BY score DESC)",
+        // Counts the entry itself and all entries with a higher or the same score.
+        RankingMode::Modified => "count(*) OVER (PARTITION BY difficulty ORDER BY score DESC)",
+        RankingMode::Ordinal => {
+            "row_number() OVER (PARTITION BY difficulty ORDER BY score DESC, creation_date ASC, \
+             id ASC)"
+        }
+    }
+}
+
+// Build the query ranking the entries shown on the leaderboard of the difficulty
+// given by the `difficulty` SQL expression, with the `hide_flagged` SQL
+// expression overriding `config.hide_flagged`. If `entry_id` is given, the entry
+// with that ID is ranked as well, even if it is not shown (e.g., because it has
+// been flagged). In best-per-player mode, only the best entry of each player
+// (identified by the case-insensitive email) is ranked, and the given entry is
+// ranked among the best entries of the other players instead.
+fn ranked_entries_query(
+    config: &LeaderboardConfig,
+    difficulty: &str,
+    hide_flagged: &str,
+    entry_id: Option<&str>,
+    period: Option<(&str, &str)>,
+) -> String {
+    let mut shown = format!(
+        "status <> 'removed' AND NOT ({} AND status = 'flagged')",
+        hide_flagged
+    );
+
+    // Both ends of the period are optional (i.e., `NULL`).
+    if let Some((since, until)) = period {
+        shown = format!(
+            "{shown} AND ({since}::TIMESTAMPTZ IS NULL OR {time} >= {since}) AND \
+             ({until}::TIMESTAMPTZ IS NULL OR {time} < {until})",
+            shown = shown,
+            since = since,
+            until = until,
+            time = SUBMISSION_TIME
+        );
+    }
+
+    let condition = match (entry_id, config.best_per_player) {
+        (None, _) => shown,
+        (Some(id), false) => format!("id = {} OR ({})", id, shown),
+        (Some(id), true) => format!(
+            "id = {id} OR ({} AND lower(email) <> (SELECT lower(email) FROM leaderboard WHERE id \
+             = {id}))",
+            shown,
+            id = id
+        ),
+    };
+
+    let entries = if config.best_per_player {
+        format!(
+            "(SELECT DISTINCT ON (lower(email)) id, name, gender, difficulty, score, creation_date \
+             FROM leaderboard WHERE difficulty = {} AND ({}) ORDER BY lower(email), score DESC, \
+             creation_date ASC, id ASC) best",
+            difficulty, condition
+        )
+    } else {
+        format!(
+            "(SELECT id, name, gender, difficulty, score, creation_date FROM leaderboard WHERE \
+             difficulty = {} AND ({})) entries",
+            difficulty, condition
         )
+    };
+
+    format!(
+        "SELECT id, name, gender, difficulty, score, creation_date, {} rank FROM {}",
+        rank_expression(config.ranking),
+        entries
+    )
+}
+
+// The entry is ranked among the entries shown on the leaderboard, even if the
+// entry itself is not shown (e.g., because it has been flagged).
+pub async fn get_ranked_score_entry(
+    client: &Transaction<'_>,
+    id: i32,
+    config: &LeaderboardConfig,
+) -> Result<Vec<LeaderboardSingleEntry>, CustomError> {
+    let score = client
+        .query(
+            "SELECT name, gender, difficulty, score FROM leaderboard WHERE id = $1",
+            &[&id],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let rank_statement = client
+        .prepare(&format!(
+            "SELECT subquery.rank FROM ({}) subquery WHERE subquery.id = $1",
+            ranked_entries_query(
+                config,
+                "(SELECT difficulty FROM leaderboard WHERE id = $1)",
+                "$2",
+                Some("$1"),
+                None
+            )
+        ))
         .await
         .map_err(|_err| CustomError::DbError)?;
 
     let rank = client
-        .query(&rank_statement, &[&id.clone()])
+        .query(&rank_statement, &[&id.clone(), &config.hide_flagged])
         .await
         .map_err(|_err| CustomError::DbError)?;
 
@@ -77,6 +390,7 @@ pub async fn add_score_entry(
         .to_string();
 
     let return_value = vec![LeaderboardSingleEntry {
+        id,
         name: score[0].get("name"),
         gender: score[0].get("gender"),
         difficulty: remapped_difficulty,
@@ -89,25 +403,50 @@ pub async fn add_score_entry(
 
 pub async fn get_score_entries(
     client: &Client,
-    limit: i64,
+    page: &LeaderboardPage,
     difficulty: i32,
+    config: &LeaderboardConfig,
 ) -> Result<Vec<LeaderboardMultipleEntries>, CustomError> {
     // If multiple score entries have the same rank due to same score, order them by
     // the time they are added to the database (while still retaining the same
-    // rank).
+    // rank). Removed entries (and flagged ones, if they are hidden) are neither
+    // shown nor ranked. The entries are ranked before skipping to the requested
+    // page, so that the ranks do not depend on the page. Cursors continue after
+    // the last entry in this order (and not after its rank, which changes when
+    // other entries are submitted or deleted).
     let statement = client
-        .prepare(
-            "SELECT name, gender, score, dense_rank() OVER (PARTITION BY difficulty ORDER BY \
-             score DESC) rank FROM leaderboard WHERE difficulty = $1 ORDER BY rank ASC, id ASC \
-             FETCH FIRST $2 ROWS ONLY",
-        )
+        .prepare(&format!(
+            "SELECT id, name, gender, score, creation_date, rank FROM ({}) ranked WHERE $5::INT IS \
+             NULL OR score < $5 OR (score = $5 AND (creation_date, id) > ($9::TIMESTAMPTZ, \
+             $6::INT)) ORDER BY {} OFFSET $4 FETCH FIRST $2 ROWS ONLY",
+            ranked_entries_query(config, "$1", "$3", None, Some(("$7", "$8"))),
+            LEADERBOARD_ORDER
+        ))
         .await
         .map_err(|_err| CustomError::DbError)?;
 
+    let (after_score, after_date, after_id) = match page.after {
+        Some((score, creation_date, id)) => (Some(score), Some(creation_date), Some(id)),
+        None => (None, None, None),
+    };
+
     // Setting `limit` and `difficulty` as parameters for the query statement this
     // way (SQL query parameterization) prevents SQL injection.
     let scores = client
-        .query(&statement, &[&difficulty, &limit])
+        .query(
+            &statement,
+            &[
+                &difficulty,
+                &page.limit,
+                &config.hide_flagged,
+                &page.offset,
+                &after_score,
+                &after_id,
+                &page.since,
+                &page.until,
+                &after_date,
+            ],
+        )
         .await
         .map_err(|_err| CustomError::DbError)?
         .iter()
@@ -120,6 +459,135 @@ pub async fn get_score_entries(
     Ok(scores)
 }
 
+// Compute the start (inclusive) and end (exclusive) of the period in the given
+// time zone (open ends are returned as `None`).
+pub async fn get_period_bounds(
+    client: &Client,
+    period: LeaderboardPeriod,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    time_zone: &str,
+) -> Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>), CustomError> {
+    if period == LeaderboardPeriod::AllTime {
+        return Ok((None, None));
+    }
+
+    let bounds = client
+        .query_one(
+            "SELECT CASE $1 WHEN 'today' THEN date_trunc('day', now() AT TIME ZONE $4) AT TIME \
+             ZONE $4 WHEN 'this-week' THEN date_trunc('week', now() AT TIME ZONE $4) AT TIME ZONE \
+             $4 ELSE $2::DATE::TIMESTAMP AT TIME ZONE $4 END AS since, CASE $1 WHEN 'range' THEN \
+             ($3::DATE + 1)::TIMESTAMP AT TIME ZONE $4 END AS until",
+            &[&period.as_str(), &from, &to, &time_zone],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok((bounds.get("since"), bounds.get("until")))
+}
+
+// Check that the database knows the given time zone, since an unknown one would
+// only make the period queries fail once they are requested.
+pub async fn check_time_zone(client: &Client, time_zone: &str) -> Result<(), StartupError> {
+    match client
+        .query_one("SELECT now() AT TIME ZONE $1", &[&time_zone])
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) if err.code() == Some(&SqlState::INVALID_PARAMETER_VALUE) => {
+            Err(StartupError::InvalidConfig(format!(
+                "LEADERBOARD__TIME_ZONE `{}` is not a known time zone",
+                time_zone
+            )))
+        }
+        Err(err) => Err(StartupError::Db(err)),
+    }
+}
+
+// Return the entry with the given ID together with up to `count` entries above
+// and below it on the leaderboard of its difficulty (or `None` if there is no
+// entry with the given ID, or if the entry has been removed or flagged while
+// flagged entries are hidden). Like for the rank returned after a submission, the
+// entry itself is otherwise included even if it is not shown on the leaderboard
+// (e.g., because it is not the best entry of its player).
+pub async fn get_score_entries_around(
+    client: &Client,
+    id: i32,
+    count: i64,
+    config: &LeaderboardConfig,
+) -> Result<Option<LeaderboardAroundResponse>, CustomError> {
+    // The entry is ranked even if it is hidden (see `ranked_entries_query`), so
+    // hidden entries have to be filtered out before.
+    let hidden = client
+        .query_opt(
+            "SELECT status = 'removed' OR ($2 AND status = 'flagged') AS hidden FROM leaderboard \
+             WHERE id = $1",
+            &[&id, &config.hide_flagged],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?
+        .is_none_or(|row| row.get("hidden"));
+
+    if hidden {
+        return Ok(None);
+    }
+
+    let statement = client
+        .prepare(&format!(
+            "WITH ranked AS ({}), numbered AS (SELECT *, row_number() OVER (ORDER BY {}) position \
+             FROM ranked) SELECT n.* FROM numbered n, numbered t WHERE t.id = $1 AND n.position \
+             BETWEEN t.position - $2 AND t.position + $2 ORDER BY n.position ASC",
+            ranked_entries_query(
+                config,
+                "(SELECT difficulty FROM leaderboard WHERE id = $1)",
+                "$3",
+                Some("$1"),
+                None
+            ),
+            LEADERBOARD_ORDER
+        ))
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let rows = client
+        .query(&statement, &[&id, &count, &config.hide_flagged])
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let index = match rows.iter().position(|row| row.get::<_, i32>("id") == id) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+
+    let map_entries = |rows: &[Row]| {
+        rows.iter()
+            .map(|row| {
+                LeaderboardMultipleEntries::from_row_ref(row)
+                    .expect("error mapping leaderboard score entries")
+            })
+            .collect::<Vec<LeaderboardMultipleEntries>>()
+    };
+
+    let row = &rows[index];
+    let entry = LeaderboardSingleEntry {
+        id,
+        name: row.get("name"),
+        gender: row.get("gender"),
+        difficulty: constants::FLIPPED_DIFFICULTY_MAP
+            .get::<i32>(&row.get::<_, i32>("difficulty"))
+            .expect("error mapping difficulty integer to string")
+            .to_string(),
+        score: row.get("score"),
+        rank: row.get("rank"),
+    };
+
+    Ok(Some(LeaderboardAroundResponse {
+        above: map_entries(&rows[..index]),
+        entry,
+        below: map_entries(&rows[index + 1..]),
+    }))
+}
+
 pub async fn get_total_materials(client: &Client) -> Result<Vec<MaterialEntry>, CustomError> {
     let statement = client
         .prepare("SELECT name, quantity FROM material ORDER BY id ASC")
@@ -136,3 +604,565 @@ pub async fn get_total_materials(client: &Client) -> Result<Vec<MaterialEntry>,
 
     Ok(materials)
 }
+
+// Insert new materials (with a quantity of zero) and update the values of the
+// existing ones, leaving their collected quantities untouched.
+pub async fn upsert_material_values(
+    client: &Client,
+    materials: &[MaterialValueEntry],
+) -> Result<u64, CustomError> {
+    let names: Vec<&str> = materials
+        .iter()
+        .map(|material| material.name.as_str())
+        .collect();
+    let values: Vec<i32> = materials.iter().map(|material| material.value).collect();
+
+    let statement = client
+        .prepare(
+            "INSERT INTO material (name, value, quantity) SELECT name, value, 0 FROM \
+             unnest($1::TEXT[], $2::INT[]) AS seed(name, value) ON CONFLICT (name) DO UPDATE SET \
+             value = EXCLUDED.value",
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let count = client
+        .execute(&statement, &[&names, &values])
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(count)
+}
+
+pub async fn get_leaderboard_export(
+    client: &Client,
+    difficulty: Option<i32>,
+) -> Result<Vec<LeaderboardExportEntry>, CustomError> {
+    // A `NULL` difficulty selects the entries of all difficulties.
+    let statement = client
+        .prepare(
+            "SELECT id, name, gender, email, difficulty, score, materials, creation_date, verdict, \
+             status, status_reason FROM leaderboard WHERE $1::INT IS NULL OR difficulty = $1 ORDER \
+             BY difficulty ASC, score DESC, id ASC",
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let entries = client
+        .query(&statement, &[&difficulty])
+        .await
+        .map_err(|_err| CustomError::DbError)?
+        .iter()
+        .map(map_leaderboard_export_entry)
+        .collect::<Vec<LeaderboardExportEntry>>();
+
+    Ok(entries)
+}
+
+fn map_leaderboard_export_entry(row: &Row) -> LeaderboardExportEntry {
+    LeaderboardExportEntry {
+        id: row.get("id"),
+        name: row.get("name"),
+        gender: row.get("gender"),
+        email: row.get("email"),
+        difficulty: constants::FLIPPED_DIFFICULTY_MAP
+            .get::<i32>(&row.get::<_, i32>("difficulty"))
+            .expect("error mapping difficulty integer to string")
+            .to_string(),
+        score: row.get("score"),
+        materials: row
+            .get::<_, Option<Json<Vec<MaterialEntry>>>>("materials")
+            .map(|materials| materials.0)
+            .unwrap_or_default(),
+        creation_date: row.get("creation_date"),
+        verdict: row.get("verdict"),
+        status: row.get("status"),
+        status_reason: row.get("status_reason"),
+    }
+}
+
+// Change the moderation status of an entry and return the updated entry (or
+// `None` if there is no entry with the given ID).
+pub async fn update_entry_status(
+    client: &Client,
+    id: i32,
+    status: EntryStatus,
+    reason: Option<&str>,
+) -> Result<Option<LeaderboardExportEntry>, CustomError> {
+    let entry = client
+        .query_opt(
+            "UPDATE leaderboard SET status = $2, status_reason = $3 WHERE id = $1 RETURNING id, \
+             name, gender, email, difficulty, score, materials, creation_date, verdict, status, \
+             status_reason",
+            &[&id, &status.as_str(), &reason],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(entry.as_ref().map(map_leaderboard_export_entry))
+}
+
+// Pick the best entry of each player (identified by the case-insensitive email)
+// per difficulty and return the top `count` players of each difficulty, with
+// ties broken by the earliest submission. Flagged and removed entries are
+// skipped.
+pub async fn get_prize_winners(
+    client: &Client,
+    count: i64,
+) -> Result<Vec<PrizeWinnerEntry>, CustomError> {
+    let statement = client
+        .prepare(
+            "SELECT difficulty, place, id, name, gender, email, score, creation_date, status FROM \
+             (SELECT *, row_number() OVER (PARTITION BY difficulty ORDER BY score DESC, \
+             creation_date ASC, id ASC) place FROM (SELECT DISTINCT ON (difficulty, lower(email)) \
+             id, name, gender, email, difficulty, score, creation_date, status FROM leaderboard \
+             WHERE status NOT IN ('flagged', 'removed') ORDER BY difficulty, lower(email), score \
+             DESC, creation_date ASC, id ASC) best) ranked WHERE place <= $1 ORDER BY difficulty \
+             ASC, place ASC",
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let winners = client
+        .query(&statement, &[&count])
+        .await
+        .map_err(|_err| CustomError::DbError)?
+        .iter()
+        .map(|row| PrizeWinnerEntry {
+            difficulty: constants::FLIPPED_DIFFICULTY_MAP
+                .get::<i32>(&row.get::<_, i32>("difficulty"))
+                .expect("error mapping difficulty integer to string")
+                .to_string(),
+            place: row.get("place"),
+            id: row.get("id"),
+            name: row.get("name"),
+            gender: row.get("gender"),
+            email: row.get("email"),
+            score: row.get("score"),
+            creation_date: row.get("creation_date"),
+            status: row.get("status"),
+        })
+        .collect::<Vec<PrizeWinnerEntry>>();
+
+    Ok(winners)
+}
+
+// Delete an entry and subtract its materials from the material totals (without
+// going below zero) in a single transaction, and return the deleted entry (or
+// `None` if there is no entry with the given ID).
+pub async fn delete_entry(
+    client: &mut Client,
+    id: i32,
+) -> Result<Option<LeaderboardExportEntry>, CustomError> {
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    // Like a score submission, lock the material totals (with the same lock that
+    // updating them takes) before locking the leaderboard entry, so that this
+    // cannot deadlock with reconciling the materials. The materials of the entry
+    // are only known once the entry has been locked, so the whole table is locked
+    // (which does not block the submissions).
+    transaction
+        .batch_execute("LOCK TABLE material IN ROW EXCLUSIVE MODE")
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let entry = match transaction
+        .query_opt(
+            "SELECT id, name, gender, email, difficulty, score, materials, creation_date, \
+             verdict, status, status_reason FROM leaderboard WHERE id = $1 FOR UPDATE",
+            &[&id],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?
+    {
+        Some(row) => map_leaderboard_export_entry(&row),
+        None => return Ok(None),
+    };
+
+    // Whatever cannot be subtracted (e.g., because the quantity has been reset
+    // in the meantime) is recorded as an adjustment instead.
+    transaction
+        .execute(
+            "UPDATE material m SET quantity = GREATEST(m.quantity - t.total, 0), adjustment = \
+             m.adjustment + GREATEST(t.total - m.quantity, 0) FROM (SELECT e ->> 'name' AS name, \
+             SUM((e ->> 'quantity')::INT) AS total FROM jsonb_array_elements($1) e GROUP BY e ->> \
+             'name') t WHERE t.name = m.name",
+            &[&Json(&entry.materials)],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    transaction
+        .execute("DELETE FROM leaderboard WHERE id = $1", &[&id])
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(Some(entry))
+}
+
+// Change the value of a material and return the updated material (or `None` if
+// there is no material with the given name).
+pub async fn update_material_value(
+    client: &Client,
+    name: &str,
+    value: i32,
+) -> Result<Option<MaterialValueEntry>, CustomError> {
+    let material = client
+        .query_opt(
+            "UPDATE material SET value = $2 WHERE name = $1 RETURNING name, value",
+            &[&name, &value],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(material
+        .map(|row| MaterialValueEntry::from_row(row).expect("error mapping material value entry")))
+}
+
+// Reset the collected quantity of a material to zero and return the updated
+// material (or `None` if there is no material with the given name). The removed
+// quantity is recorded as an adjustment, so that reconciling the materials keeps
+// the reset.
+pub async fn reset_material_quantity(
+    client: &Client,
+    name: &str,
+) -> Result<Option<MaterialEntry>, CustomError> {
+    let material = client
+        .query_opt(
+            "UPDATE material SET adjustment = adjustment - quantity, quantity = 0 WHERE name = $1 \
+             RETURNING name, quantity",
+            &[&name],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(material.map(|row| MaterialEntry::from_row(row).expect("error mapping material entry")))
+}
+
+// Recompute the total quantity of each material from the `materials` column of
+// all leaderboard entries and return the materials whose recorded quantity has
+// drifted from that total plus the recorded adjustment of the material. If
+// `repair` is set, the recorded quantities are also overwritten with the
+// expected quantities. Both happen in a single transaction, during which new
+// score submissions are blocked so that the totals stay consistent.
+pub async fn reconcile_materials(
+    client: &mut Client,
+    repair: bool,
+) -> Result<Vec<MaterialDrift>, CustomError> {
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    // Take the locks in the same order as a score submission (which updates the
+    // material totals before inserting its entry) so that the two cannot
+    // deadlock. A table lock is used (instead of locking the rows) since a
+    // submission might lock the material rows in any order.
+    transaction
+        .batch_execute(
+            "LOCK TABLE material IN EXCLUSIVE MODE; LOCK TABLE leaderboard IN SHARE MODE",
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let drift = transaction
+        .query(
+            "SELECT m.name, m.quantity AS recorded, (COALESCE(t.total, 0) + m.adjustment)::BIGINT \
+             AS expected FROM material m LEFT JOIN (SELECT e ->> 'name' AS name, SUM((e ->> \
+             'quantity')::INT) AS total FROM leaderboard, jsonb_array_elements(COALESCE(materials, \
+             '[]'::JSONB)) e GROUP BY e ->> 'name') t ON t.name = m.name WHERE m.quantity <> \
+             COALESCE(t.total, 0) + m.adjustment ORDER BY m.id ASC",
+            &[],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?
+        .iter()
+        .map(|row| MaterialDrift {
+            name: row.get("name"),
+            recorded: row.get("recorded"),
+            expected: row.get("expected"),
+        })
+        .collect::<Vec<MaterialDrift>>();
+
+    if repair && !drift.is_empty() {
+        // Every accepted submission is recorded in both tables and every other
+        // change of the quantities is recorded as an adjustment, so the
+        // leaderboard entries (plus the adjustments) are treated as the source of
+        // truth.
+        transaction
+            .execute(
+                "UPDATE material m SET quantity = COALESCE((SELECT SUM((e ->> 'quantity')::INT) \
+                 FROM leaderboard, jsonb_array_elements(COALESCE(materials, '[]'::JSONB)) e \
+                 WHERE e ->> 'name' = m.name), 0) + m.adjustment",
+                &[],
+            )
+            .await
+            .map_err(|_err| CustomError::DbError)?;
+
+        unlock_goals(&transaction).await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(drift)
+}
+
+// Record quantities which have been added to the material totals without a
+// corresponding leaderboard entry (e.g., by importing old submissions), so that
+// reconciling the materials keeps them.
+pub async fn add_material_adjustments(
+    client: &Transaction<'_>,
+    materials: &[MaterialEntry],
+) -> Result<(), CustomError> {
+    for material in materials.iter() {
+        client
+            .execute(
+                "UPDATE material SET adjustment = adjustment + $2 WHERE name = $1",
+                &[&material.name, &material.quantity],
+            )
+            .await
+            .map_err(|_err| CustomError::DbError)?;
+    }
+
+    Ok(())
+}
+
+// Mark the goals whose required materials have all been collected as unlocked.
+// Goals stay unlocked (with the time at which they have first been reached) even
+// if the material quantities are decreased later on.
+pub async fn unlock_goals(client: &Transaction<'_>) -> Result<u64, CustomError> {
+    client
+        .execute(
+            "UPDATE goal g SET unlocked_at = now() WHERE unlocked_at IS NULL AND EXISTS (SELECT 1 \
+             FROM goal_requirement r WHERE r.goal_id = g.id) AND NOT EXISTS (SELECT 1 FROM \
+             goal_requirement r JOIN material m ON m.id = r.material_id WHERE r.goal_id = g.id AND \
+             m.quantity < r.quantity)",
+            &[],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)
+}
+
+// Insert or update the goals (identified by their names) and replace their
+// required materials. Updated goals keep the time at which they have been
+// unlocked, unless their required materials have changed (in which case they are
+// unlocked again right away if they are still reached).
+pub async fn upsert_goals(
+    client: &mut Client,
+    goals: &[GoalDefinition],
+) -> Result<usize, CustomError> {
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    for goal in goals.iter() {
+        let mapped_difficulty = constants::DIFFICULTY_MAP
+            .get::<str>(&goal.difficulty)
+            .ok_or(CustomError::ValidationError)?;
+
+        let id: i32 = transaction
+            .query_one(
+                "INSERT INTO goal (name, difficulty) VALUES ($1, $2) ON CONFLICT (name) DO UPDATE \
+                 SET difficulty = EXCLUDED.difficulty RETURNING id",
+                &[&goal.name, mapped_difficulty],
+            )
+            .await
+            .map_err(|_err| CustomError::DbError)?
+            .get("id");
+
+        let mut previous: Vec<(String, i32)> = transaction
+            .query(
+                "DELETE FROM goal_requirement r USING material m WHERE r.goal_id = $1 AND m.id = \
+                 r.material_id RETURNING m.name, r.quantity",
+                &[&id],
+            )
+            .await
+            .map_err(|_err| CustomError::DbError)?
+            .iter()
+            .map(|row| (row.get("name"), row.get("quantity")))
+            .collect();
+        previous.sort();
+
+        let mut requirements: Vec<(String, i32)> = goal
+            .materials
+            .iter()
+            .map(|material| (material.name.clone(), material.quantity))
+            .collect();
+        requirements.sort();
+
+        if previous != requirements {
+            transaction
+                .execute("UPDATE goal SET unlocked_at = NULL WHERE id = $1", &[&id])
+                .await
+                .map_err(|_err| CustomError::DbError)?;
+        }
+
+        let names: Vec<&str> = goal
+            .materials
+            .iter()
+            .map(|material| material.name.as_str())
+            .collect();
+        let quantities: Vec<i32> = goal
+            .materials
+            .iter()
+            .map(|material| material.quantity)
+            .collect();
+
+        let inserted = transaction
+            .execute(
+                "INSERT INTO goal_requirement (goal_id, material_id, quantity) SELECT $1, m.id, \
+                 seed.quantity FROM unnest($2::TEXT[], $3::INT[]) AS seed(name, quantity) JOIN \
+                 material m ON m.name = seed.name",
+                &[&id, &names, &quantities],
+            )
+            .await
+            .map_err(|_err| CustomError::DbError)?;
+
+        // Unknown materials would otherwise be silently skipped.
+        if inserted as usize != goal.materials.len() {
+            return Err(CustomError::ValidationError);
+        }
+    }
+
+    unlock_goals(&transaction).await?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    Ok(goals.len())
+}
+
+pub async fn get_goal_progress(client: &Client) -> Result<Vec<GoalProgress>, CustomError> {
+    let rows = client
+        .query(
+            "SELECT g.id, g.name, g.difficulty, g.unlocked_at, m.name AS material, m.quantity, \
+             r.quantity AS required FROM goal g JOIN goal_requirement r ON r.goal_id = g.id JOIN \
+             material m ON m.id = r.material_id ORDER BY g.difficulty ASC, g.id ASC, m.id ASC",
+            &[],
+        )
+        .await
+        .map_err(|_err| CustomError::DbError)?;
+
+    let mut goals: Vec<(i32, GoalProgress)> = vec![];
+
+    for row in rows.iter() {
+        let material = GoalMaterialProgress {
+            name: row.get("material"),
+            quantity: row.get("quantity"),
+            required: row.get("required"),
+        };
+
+        match goals.last_mut() {
+            Some((id, goal)) if *id == row.get::<_, i32>("id") => goal.materials.push(material),
+            _ => goals.push((
+                row.get("id"),
+                GoalProgress {
+                    name: row.get("name"),
+                    difficulty: constants::FLIPPED_DIFFICULTY_MAP
+                        .get::<i32>(&row.get::<_, i32>("difficulty"))
+                        .expect("error mapping difficulty integer to string")
+                        .to_string(),
+                    progress: 0.0,
+                    unlocked_at: row.get("unlocked_at"),
+                    materials: vec![material],
+                },
+            )),
+        }
+    }
+
+    Ok(goals
+        .into_iter()
+        .map(|(_, mut goal)| {
+            goal.progress = utils::calculate_goal_progress(&goal.materials);
+            goal
+        })
+        .collect())
+}
+
+// Define unit tests for the database TLS setup.
+#[cfg(test)]
+mod tests {
+    use super::configure_tls;
+    use crate::{
+        config::{PgSslMode, PgTlsConfig},
+        errors::StartupError,
+    };
+
+    #[test]
+    fn test_parse_sslmode() {
+        for (value, mode) in [
+            ("disable", PgSslMode::Disable),
+            ("prefer", PgSslMode::Prefer),
+            ("require", PgSslMode::Require),
+            ("verify-ca", PgSslMode::VerifyCa),
+            ("verify-full", PgSslMode::VerifyFull),
+        ] {
+            let parsed: PgSslMode =
+                serde_json::from_value(serde_json::json!(value)).expect("error parsing sslmode");
+            assert_eq!(parsed, mode, "`{}` should be parsed as {:?}", value, mode);
+        }
+
+        assert!(serde_json::from_value::<PgSslMode>(serde_json::json!("allow")).is_err());
+        assert!(serde_json::from_value::<PgSslMode>(serde_json::json!("verify_full")).is_err());
+    }
+
+    #[test]
+    fn test_sslmode_without_root_certificate() {
+        for sslmode in [PgSslMode::Disable, PgSslMode::Prefer, PgSslMode::Require] {
+            let config = PgTlsConfig {
+                sslmode,
+                sslrootcert: None,
+            };
+
+            assert!(
+                configure_tls(&config).is_ok(),
+                "{:?} should not require a root certificate",
+                sslmode
+            );
+        }
+
+        for sslmode in [PgSslMode::VerifyCa, PgSslMode::VerifyFull] {
+            let config = PgTlsConfig {
+                sslmode,
+                sslrootcert: None,
+            };
+
+            match configure_tls(&config) {
+                Err(StartupError::InvalidConfig(_)) => {}
+                _ => panic!(
+                    "{:?} without a root certificate should be rejected",
+                    sslmode
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_root_certificate_file() {
+        let config = PgTlsConfig {
+            sslmode: PgSslMode::VerifyFull,
+            sslrootcert: Some("tls/does_not_exist.pem".to_string()),
+        };
+
+        match configure_tls(&config) {
+            Err(StartupError::DbCaFile { path, .. }) => {
+                assert_eq!(path, "tls/does_not_exist.pem".to_string())
+            }
+            _ => panic!("missing root certificate file should return a CA file error"),
+        }
+    }
+}
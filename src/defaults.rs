@@ -1,5 +1,9 @@
 // Define default values here for optional query parameters and optional JSON
 // model payload keys.
+use crate::{
+    config::{PgSslMode, PlausibilityAction, RankingMode, TlsVersion},
+    models::ExportFormat,
+};
 
 pub fn default_max_entries() -> i64 {
     10
@@ -12,3 +16,141 @@ pub fn default_difficulty() -> String {
 pub fn default_bonus() -> i32 {
     0
 }
+
+pub fn default_around_count() -> i64 {
+    5
+}
+
+pub fn default_prize_winners_count() -> i64 {
+    3
+}
+
+pub fn default_export_format() -> ExportFormat {
+    ExportFormat::Json
+}
+
+// Define default values here for optional configuration keys.
+
+pub fn default_allowed_hosts() -> Vec<String> {
+    vec!["sutdoverdue.dev".to_string()]
+}
+
+pub fn default_keep_alive() -> usize {
+    150
+}
+
+pub fn default_score_payload_limit() -> usize {
+    1024
+}
+
+pub fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+pub fn default_max_score() -> i32 {
+    4060
+}
+
+pub fn default_min_score() -> i32 {
+    0
+}
+
+pub fn default_max_materials() -> i32 {
+    10
+}
+
+pub fn default_min_materials() -> i32 {
+    0
+}
+
+pub fn default_max_bonus_value() -> i32 {
+    10
+}
+
+pub fn default_min_bonus_value() -> i32 {
+    0
+}
+
+pub fn default_idempotency_window() -> u64 {
+    600
+}
+
+pub fn default_session_lifetime() -> u64 {
+    3600
+}
+
+pub fn default_session_min_duration() -> u64 {
+    10
+}
+
+pub fn default_plausibility_action() -> PlausibilityAction {
+    PlausibilityAction::Flag
+}
+
+pub fn default_plausible_score_per_second() -> f64 {
+    20.0
+}
+
+pub fn default_plausible_materials_per_minute() -> f64 {
+    30.0
+}
+
+pub fn default_ranking_mode() -> RankingMode {
+    RankingMode::Dense
+}
+
+pub fn default_leaderboard_time_zone() -> String {
+    "Asia/Singapore".to_string()
+}
+
+pub fn default_max_leaderboard_length() -> i64 {
+    100
+}
+
+pub fn default_min_leaderboard_length() -> i64 {
+    1
+}
+
+pub fn default_live_leaderboard_length() -> i64 {
+    10
+}
+
+pub fn default_rate_limit_interval() -> u64 {
+    60
+}
+
+pub fn default_rate_limit_max_requests() -> usize {
+    60
+}
+
+pub fn default_cors_allowed_origins() -> Vec<String> {
+    vec![
+        "https://sutd-fablab-game.netlify.app".to_string(),
+        "https://overdue.sutd.edu.sg".to_string(),
+        "https://openhouse.sutd.edu.sg".to_string(),
+    ]
+}
+
+pub fn default_cors_max_age() -> usize {
+    150
+}
+
+pub fn default_pg_sslmode() -> PgSslMode {
+    PgSslMode::Disable
+}
+
+pub fn default_tls_cert_chain() -> String {
+    "tls/fullchain.pem".to_string()
+}
+
+pub fn default_tls_key() -> String {
+    "tls/privkey.pem".to_string()
+}
+
+pub fn default_tls_min_version() -> TlsVersion {
+    TlsVersion::Tls1_2
+}
+
+pub fn default_tls_reload_interval() -> u64 {
+    60
+}
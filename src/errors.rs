@@ -1,4 +1,6 @@
 use actix_web::{error, http::StatusCode, HttpRequest, HttpResponse};
+use config::ConfigError;
+use openssl::error::ErrorStack;
 use thiserror::Error;
 
 use crate::models::ErrorResponse;
@@ -13,12 +15,56 @@ pub enum CustomError {
     BadRequest,
     #[error("Attempted access to the specified resource is forbidden.")]
     Forbidden,
+    #[error("The request conflicts with an earlier request.")]
+    Conflict,
     #[error("A database error has occurred.")]
     DbError,
     #[error("An internal server error has occurred.")]
     Internal,
 }
 
+// Errors that can occur while the server is being set up (before any request is
+// handled) or while running one of the maintenance subcommands.
+#[derive(Error, Debug)]
+pub enum StartupError {
+    #[error("error getting configuration from environment: {0}")]
+    Config(#[from] ConfigError),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("error loading TLS file `{path}`: {source}")]
+    TlsFile { path: String, source: ErrorStack },
+    #[error("error reading database CA certificate `{path}`: {source}")]
+    DbCaFile {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("error configuring database TLS: {0}")]
+    DbTls(#[from] native_tls::Error),
+    #[error("error creating database pool: {0}")]
+    Pool(#[from] deadpool_postgres::config::ConfigError),
+    #[error("error getting database client: {0}")]
+    DbPool(#[from] deadpool_postgres::PoolError),
+    #[error("database error: {0}")]
+    Db(#[from] tokio_postgres::Error),
+    #[error("error configuring TLS acceptor: {0}")]
+    Tls(#[from] ErrorStack),
+    #[error("error starting server: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("error accessing file `{path}`: {source}")]
+    File {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    #[error("error parsing JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("error writing CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("error running command: {0}")]
+    Command(#[from] CustomError),
+}
+
 impl CustomError {
     pub fn name(&self) -> String {
         match self {
@@ -26,6 +72,7 @@ impl CustomError {
             Self::NotFound => "Not Found".to_string(),
             Self::BadRequest => "Bad Request".to_string(),
             Self::Forbidden => "Forbidden Error".to_string(),
+            Self::Conflict => "Conflict Error".to_string(),
             Self::DbError => "Database Error".to_string(),
             Self::Internal => "Internal Server Error".to_string(),
         }
@@ -39,6 +86,7 @@ impl error::ResponseError for CustomError {
             Self::NotFound => StatusCode::NOT_FOUND,
             Self::BadRequest => StatusCode::BAD_REQUEST,
             Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Conflict => StatusCode::CONFLICT,
             Self::DbError => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -179,6 +227,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_default_message_conflict() {
+        let conflict: CustomError = CustomError::Conflict;
+
+        assert_eq!(
+            conflict.status_code(),
+            StatusCode::CONFLICT,
+            "Default status code should be shown"
+        );
+        assert_eq!(
+            conflict.name(),
+            "Conflict Error".to_string(),
+            "Default name should be shown"
+        );
+        assert_eq!(
+            conflict.to_string(),
+            "The request conflicts with an earlier request.".to_string(),
+            "Default message should be shown"
+        );
+    }
+
     #[test]
     fn test_default_message_db_error() {
         let db_error: CustomError = CustomError::DbError;
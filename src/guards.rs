@@ -0,0 +1,119 @@
+// Define a `Host` guard which accepts any host name from the configured list.
+use actix_web::{dev::RequestHead, guard::Guard, http::header};
+use std::sync::Arc;
+
+use crate::utils::strip_port;
+
+// Each pattern is either an exact host name (e.g., `sutdoverdue.dev`), a
+// wildcard matching any subdomain (e.g., `*.sutdoverdue.dev`, which does not
+// match `sutdoverdue.dev` itself) or `*` to accept any host (including requests
+// without a `Host` header) for local development.
+#[derive(Clone)]
+pub struct AllowedHosts {
+    patterns: Arc<Vec<String>>,
+}
+
+impl AllowedHosts {
+    pub fn new(patterns: &[String]) -> Self {
+        Self {
+            patterns: Arc::new(
+                patterns
+                    .iter()
+                    .map(|pattern| pattern.to_ascii_lowercase())
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn allows_any(&self) -> bool {
+        self.patterns.iter().any(|pattern| pattern == "*")
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        // Host names are case-insensitive.
+        let host = host.to_ascii_lowercase();
+
+        self.patterns.iter().any(|pattern| {
+            if pattern == "*" {
+                return true;
+            }
+
+            match pattern.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+                None => *pattern == host,
+            }
+        })
+    }
+}
+
+impl Guard for AllowedHosts {
+    fn check(&self, req: &RequestHead) -> bool {
+        if self.allows_any() {
+            return true;
+        }
+
+        let host = req
+            .headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri.host());
+
+        match host {
+            Some(host) => self.matches(strip_port(host)),
+            None => false,
+        }
+    }
+}
+
+// Define unit tests for the host name matching logic.
+#[cfg(test)]
+mod tests {
+    use super::AllowedHosts;
+    use crate::utils::strip_port;
+
+    #[test]
+    fn test_exact_host_names() {
+        let hosts = AllowedHosts::new(&[
+            "sutdoverdue.dev".to_string(),
+            "staging.sutdoverdue.dev".to_string(),
+        ]);
+
+        assert!(hosts.matches("sutdoverdue.dev"));
+        assert!(hosts.matches("SUTDOverdue.dev"));
+        assert!(hosts.matches("staging.sutdoverdue.dev"));
+        assert!(!hosts.matches("overdue.sutd.edu.sg"));
+        assert!(!hosts.matches("kiosk.sutdoverdue.dev"));
+    }
+
+    #[test]
+    fn test_wildcard_subdomains() {
+        let hosts = AllowedHosts::new(&["*.sutdoverdue.dev".to_string()]);
+
+        assert!(hosts.matches("staging.sutdoverdue.dev"));
+        assert!(hosts.matches("a.b.sutdoverdue.dev"));
+        assert!(!hosts.matches("sutdoverdue.dev"));
+        assert!(!hosts.matches(".sutdoverdue.dev"));
+        assert!(!hosts.matches("evilsutdoverdue.dev"));
+    }
+
+    #[test]
+    fn test_bracketed_ipv6_addresses() {
+        let hosts = AllowedHosts::new(&["[::1]".to_string(), "[2001:DB8::1]".to_string()]);
+
+        assert!(hosts.matches(strip_port("[::1]:8443")));
+        assert!(hosts.matches("[2001:db8::1]"));
+        assert!(!hosts.matches("[::2]"));
+        assert!(!hosts.matches("::1"));
+    }
+
+    #[test]
+    fn test_any_host() {
+        let hosts = AllowedHosts::new(&["*".to_string()]);
+
+        assert!(hosts.allows_any());
+        assert!(hosts.matches("localhost"));
+        assert!(hosts.matches("192.168.1.10"));
+    }
+}
@@ -1,11 +1,25 @@
 use actix_files as fs;
-use actix_web::{http::StatusCode, web, HttpResponse, Responder};
+use actix_web::{
+    dev::BodyEncoding,
+    http::{header, ContentEncoding, Method, StatusCode},
+    web, HttpRequest, HttpResponse, Responder,
+};
+use actix_web_actors::ws;
+use chrono::Utc;
 use deadpool_postgres::{Client, Pool};
-use slog::{crit, error, o, Logger};
+use slog::{crit, error, info, o, warn, Logger};
 use std::{io, process::Command};
 use validator::Validate;
 
-use crate::{constants, db, errors::CustomError, models, utils};
+use crate::{
+    auth,
+    config::{self, PlausibilityAction},
+    constants, db,
+    errors::{self, CustomError},
+    models,
+    plausibility::{self, Verdict},
+    realtime, sessions, utils,
+};
 
 pub async fn get_client(pool: Pool, log: Logger) -> Result<Client, CustomError> {
     pool.get().await.map_err(|err| {
@@ -24,8 +38,43 @@ pub fn log_error(log: Logger) -> impl Fn(CustomError) -> CustomError {
     }
 }
 
+// Issue a signed session token when a game is started. The token must be sent
+// along with the score at the end of the game.
+pub async fn start_session(
+    state: web::Data<models::AppState>,
+    item: web::Json<models::StartSessionRequest>,
+) -> Result<impl Responder, CustomError> {
+    match item.validate() {
+        Ok(_) => (),
+        Err(_e) => return Err(CustomError::ValidationError),
+    }
+
+    let log = state.log.new(o!("handler" => "start_session"));
+    let config = &state.config.session;
+    let secret = config.secret.as_deref().ok_or(CustomError::Internal)?;
+    let started_at = Utc::now().timestamp();
+
+    let result = sessions::issue_token(secret, &item.difficulty, started_at).map(|session_token| {
+        models::StartSessionResponse {
+            session_token,
+            expires_at: started_at + config.lifetime as i64,
+        }
+    });
+
+    result
+        .map(|session| {
+            HttpResponse::Ok()
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block")
+                .json(session)
+        })
+        .map_err(log_error(log))
+}
+
 // This handler uses JSON extractor with limit.
 pub async fn submit_score(
+    req: HttpRequest,
     state: web::Data<models::AppState>,
     item: web::Json<models::ScoreEntry>,
 ) -> Result<impl Responder, CustomError> {
@@ -35,23 +84,24 @@ pub async fn submit_score(
         Err(_e) => return Err(CustomError::ValidationError),
     }
 
-    // Validate each material.
-    if !item.materials.is_empty() {
-        for material in item.materials.clone().iter() {
-            match material.validate() {
-                Ok(_) => (),
-                Err(_e) => return Err(CustomError::ValidationError),
-            }
-        }
+    // Validate the score, bonus and each material against the configured limits.
+    if !utils::check_if_entry_within_limits(&item, &state.config.scoring) {
+        return Err(CustomError::ValidationError);
     }
 
-    let log = state.log.new(o!("handler" => "submit_score"));
+    // Retries of the same submission (e.g., on flaky connections) are identified
+    // by the optional idempotency key header.
+    let idempotency_key = match req.headers().get(constants::IDEMPOTENCY_KEY_HEADER) {
+        Some(key) => match key.to_str() {
+            Ok(key) if utils::check_if_idempotency_key_valid(key) => Some(key),
+            _ => return Err(CustomError::ValidationError),
+        },
+        None => None,
+    };
 
-    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    let log = state.log.new(o!("handler" => "submit_score"));
 
-    // By default, return error.
-    let mut result: Result<Vec<models::LeaderboardSingleEntry>, CustomError> =
-        Err(CustomError::Internal);
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
 
     let values = utils::get_values_of_materials(&client)
         .await
@@ -68,26 +118,70 @@ pub async fn submit_score(
     .await
     .map_err(|_err| CustomError::ValidationError)?;
 
-    if allowed_to_add_score {
-        // Do not need to add any materials to aggregate if material vector is empty.
-        let added_to_materials: bool = if !item.materials.is_empty() {
-            utils::add_materials_to_aggregate(&client, item.materials.clone())
-                .await
-                .map_err(|_err| CustomError::DbError)?
-        } else {
-            true
-        };
-
-        // Only add score entry if there are materials added to the material table.
-        if added_to_materials {
-            result = Ok(db::add_score_entry(&client, item)
-                .await
-                .map_err(|_err| CustomError::DbError)?);
-        }
-    } else {
+    if !allowed_to_add_score {
         return Err(CustomError::ValidationError);
     }
 
+    // Only accept scores from sessions that have been started via
+    // `/start_session` for the same difficulty, have lasted long enough for a
+    // game and have not expired yet.
+    let config = &state.config.session;
+    let session = match &item.session_token {
+        Some(token) => sessions::verify_token(
+            config.secret.as_deref().ok_or(CustomError::Internal)?,
+            token,
+        )?,
+        None => return Err(CustomError::Forbidden),
+    };
+
+    if !sessions::check_if_session_valid(&session, &item.difficulty, Utc::now().timestamp(), config)
+    {
+        return Err(CustomError::Forbidden);
+    }
+
+    // Judge the entry against the per-difficulty limits of the plausibility model.
+    let elapsed = Utc::now().timestamp() - session.started_at;
+    let verdict = plausibility::assess_entry(&item, elapsed, &state.config.plausibility);
+
+    if verdict == Verdict::Implausible {
+        warn!(log, "Implausible score submission"; "score" => item.score, "elapsed" => elapsed);
+
+        if state.config.plausibility.action == PlausibilityAction::Reject {
+            return Err(CustomError::Forbidden);
+        }
+    }
+
+    let difficulty = item.difficulty.clone();
+    let has_materials = !item.materials.is_empty();
+
+    let result = db::submit_score_entry(
+        &mut client,
+        item,
+        idempotency_key,
+        &session,
+        verdict,
+        &state.config,
+    )
+    .await;
+
+    // Push the new top entries and material totals to the subscribers (without
+    // failing the already committed submission).
+    if result.is_ok() && state.leaderboard_hub.has_subscribers(&difficulty) {
+        if let Err(_err) = state
+            .leaderboard_hub
+            .refresh(&client, &difficulty, &state.config)
+            .await
+        {
+            warn!(log, "Error fetching the live leaderboard");
+        }
+    }
+
+    if result.is_ok() && has_materials && state.material_hub.has_subscribers() {
+        if let Err(_err) = state.material_hub.refresh(&client).await {
+            warn!(log, "Error fetching the material totals to push");
+        }
+    }
+
     // Echo JSON response partially back if everything is okay (follow standard
     // military communication procedure, protocol & etiquette).
     result
@@ -96,11 +190,25 @@ pub async fn submit_score(
                 .header("Content-Security-Policy", "default-src 'self'")
                 .header("Strict-Transport-Security", "max-age=3600")
                 .header("X-XSS-Protection", "1; mode=block")
-                .json(score.get(0))
+                .json(score.first())
         })
         .map_err(log_error(log))
 }
 
+// Upgrade the connection to a WebSocket which pushes the top entries of the
+// subscribed difficulties (see `realtime`).
+pub async fn get_live_leaderboard(
+    req: HttpRequest,
+    stream: web::Payload,
+    state: web::Data<models::AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    ws::start(
+        realtime::LeaderboardSocket::new(state.get_ref().clone()),
+        &req,
+        stream,
+    )
+}
+
 pub async fn get_leaderboard(
     state: web::Data<models::AppState>,
     web::Query(query): web::Query<models::LeaderboardQueryRequest>,
@@ -110,6 +218,36 @@ pub async fn get_leaderboard(
         Err(_e) => return Err(CustomError::ValidationError),
     }
 
+    let limits = &state.config.leaderboard;
+    if query.max_entries < limits.min_length || query.max_entries > limits.max_length {
+        return Err(CustomError::ValidationError);
+    }
+
+    // Only one of `offset` and `cursor` can be given.
+    let after = match (&query.offset, &query.cursor) {
+        (Some(_), Some(_)) => return Err(CustomError::ValidationError),
+        (_, Some(cursor)) => {
+            Some(utils::decode_cursor(cursor).ok_or(CustomError::ValidationError)?)
+        }
+        _ => None,
+    };
+
+    // The `from` and `to` dates imply (and are only allowed with) the `range`
+    // period.
+    let dated = query.from.is_some() || query.to.is_some();
+    let period = match query.period {
+        None if dated => models::LeaderboardPeriod::Range,
+        Some(models::LeaderboardPeriod::Range) if dated => models::LeaderboardPeriod::Range,
+        Some(models::LeaderboardPeriod::Range) => return Err(CustomError::ValidationError),
+        Some(_) if dated => return Err(CustomError::ValidationError),
+        period => period.unwrap_or(models::LeaderboardPeriod::AllTime),
+    };
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(CustomError::ValidationError);
+        }
+    }
+
     let log = state.log.new(o!("handler" => "get_leaderboard"));
 
     let client: Client = get_client(state.pool.clone(), log.clone()).await?;
@@ -120,9 +258,83 @@ pub async fn get_leaderboard(
         .get::<str>(&query.difficulty.clone())
         .expect("error mapping difficulty string to integer");
 
-    let result = db::get_score_entries(&client, query.max_entries, *mapped_difficulty).await;
+    let leaderboard = config::LeaderboardConfig {
+        best_per_player: query.best_per_player.unwrap_or(limits.best_per_player),
+        ..limits.clone()
+    };
+
+    let (since, until) =
+        db::get_period_bounds(&client, period, query.from, query.to, &limits.time_zone).await?;
+
+    // Fetch one more entry than requested to find out whether there is a next
+    // page.
+    let page = models::LeaderboardPage {
+        limit: query.max_entries + 1,
+        offset: query.offset.unwrap_or(0),
+        after,
+        since,
+        until,
+    };
+
+    let result = db::get_score_entries(&client, &page, *mapped_difficulty, &leaderboard).await;
 
     result
+        .map(|mut scores| {
+            let has_next_page = scores.len() as i64 > query.max_entries;
+            scores.truncate(query.max_entries as usize);
+
+            let mut response = HttpResponse::Ok();
+            response
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block");
+
+            // Keep returning a bare array to clients which do not use pagination
+            // (such as the shipped game client).
+            if query.offset.is_none() && query.cursor.is_none() {
+                return response.json(scores);
+            }
+
+            let next_cursor = match scores.last() {
+                Some(last) if has_next_page => last
+                    .creation_date
+                    .map(|creation_date| utils::encode_cursor(last.score, creation_date, last.id)),
+                _ => None,
+            };
+
+            response.json(models::LeaderboardPageResponse {
+                entries: scores,
+                next_cursor,
+            })
+        })
+        .map_err(log_error(log))
+}
+
+// Get an entry (e.g., the one that has just been submitted) together with its
+// neighbors on the leaderboard.
+pub async fn get_leaderboard_around(
+    state: web::Data<models::AppState>,
+    web::Query(query): web::Query<models::LeaderboardAroundQueryRequest>,
+) -> Result<impl Responder, CustomError> {
+    match query.validate() {
+        Ok(_) => (),
+        Err(_e) => return Err(CustomError::ValidationError),
+    }
+
+    let log = state.log.new(o!("handler" => "get_leaderboard_around"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let config = &state.config.leaderboard;
+    let leaderboard = config::LeaderboardConfig {
+        best_per_player: query.best_per_player.unwrap_or(config.best_per_player),
+        ..config.clone()
+    };
+
+    let result = db::get_score_entries_around(&client, query.id, query.count, &leaderboard).await;
+
+    result
+        .and_then(|scores| scores.ok_or(CustomError::NotFound))
         .map(|scores| {
             HttpResponse::Ok()
                 .header("Content-Security-Policy", "default-src 'self'")
@@ -153,6 +365,296 @@ pub async fn get_materials(
         .map_err(log_error(log))
 }
 
+// Get the progress of each community goal towards being unlocked.
+pub async fn get_goals(state: web::Data<models::AppState>) -> Result<impl Responder, CustomError> {
+    let log = state.log.new(o!("handler" => "get_goals"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_goal_progress(&client).await;
+
+    result
+        .map(|goals| {
+            HttpResponse::Ok()
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block")
+                .json(goals)
+        })
+        .map_err(log_error(log))
+}
+
+// Stream the material totals as server-sent events (see `realtime`).
+pub async fn get_materials_stream(
+    req: HttpRequest,
+    state: web::Data<models::AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string());
+
+    let log = state.log.new(o!("handler" => "get_materials_stream"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    // Catch up with any changes which have not been pushed (e.g., made while
+    // nobody was subscribed) when subscribing.
+    let events = state
+        .material_hub
+        .subscribe(&client, last_event_id.as_deref())
+        .await
+        .map_err(log_error(log))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Strict-Transport-Security", "max-age=3600")
+        // Compressing the stream would hold back the events.
+        .encoding(ContentEncoding::Identity)
+        .streaming(realtime::with_heartbeats(events)))
+}
+
+// List the raw leaderboard entries (including the players' emails and the
+// moderation status) of all difficulties or of the given difficulty.
+pub async fn list_entries(
+    req: HttpRequest,
+    state: web::Data<models::AppState>,
+    web::Query(query): web::Query<models::AdminEntriesQueryRequest>,
+) -> Result<impl Responder, CustomError> {
+    auth::check_if_admin_authorized(&req, &state.config.admin)?;
+
+    match query.validate() {
+        Ok(_) => (),
+        Err(_e) => return Err(CustomError::ValidationError),
+    }
+
+    let log = state.log.new(o!("handler" => "list_entries"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let mapped_difficulty = query.difficulty.map(|difficulty| {
+        *constants::DIFFICULTY_MAP
+            .get::<str>(&difficulty)
+            .expect("error mapping difficulty string to integer")
+    });
+
+    let result = db::get_leaderboard_export(&client, mapped_difficulty).await;
+
+    result
+        .map(|entries| {
+            HttpResponse::Ok()
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block")
+                .json(entries)
+        })
+        .map_err(log_error(log))
+}
+
+// Permanently delete a leaderboard entry (together with its contribution to the
+// material totals).
+pub async fn delete_entry(
+    req: HttpRequest,
+    state: web::Data<models::AppState>,
+    web::Path(id): web::Path<i32>,
+) -> Result<impl Responder, CustomError> {
+    auth::check_if_admin_authorized(&req, &state.config.admin)?;
+
+    let log = state.log.new(o!("handler" => "delete_entry"));
+
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::delete_entry(&mut client, id).await;
+
+    // Push the new top entries and material totals to the subscribers (without
+    // failing the already committed deletion).
+    if let Ok(Some(entry)) = &result {
+        if state.leaderboard_hub.has_subscribers(&entry.difficulty) {
+            if let Err(_err) = state
+                .leaderboard_hub
+                .refresh(&client, &entry.difficulty, &state.config)
+                .await
+            {
+                warn!(log, "Error fetching the live leaderboard");
+            }
+        }
+
+        if !entry.materials.is_empty() && state.material_hub.has_subscribers() {
+            if let Err(_err) = state.material_hub.refresh(&client).await {
+                warn!(log, "Error fetching the material totals to push");
+            }
+        }
+    }
+
+    result
+        .and_then(|entry| entry.ok_or(CustomError::NotFound))
+        .map(|entry| {
+            info!(log, "Deleted leaderboard entry"; "id" => id);
+
+            HttpResponse::Ok()
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block")
+                .json(entry)
+        })
+        .map_err(log_error(log))
+}
+
+// Change the moderation status of a leaderboard entry (e.g., to approve it for a
+// prize or to remove it from the leaderboard).
+pub async fn update_entry_status(
+    req: HttpRequest,
+    state: web::Data<models::AppState>,
+    web::Path(id): web::Path<i32>,
+    item: web::Json<models::UpdateEntryStatusRequest>,
+) -> Result<impl Responder, CustomError> {
+    auth::check_if_admin_authorized(&req, &state.config.admin)?;
+
+    match item.validate() {
+        Ok(_) => (),
+        Err(_e) => return Err(CustomError::ValidationError),
+    }
+
+    let log = state.log.new(o!("handler" => "update_entry_status"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::update_entry_status(&client, id, item.status, item.reason.as_deref()).await;
+
+    result
+        .and_then(|entry| entry.ok_or(CustomError::NotFound))
+        .map(|entry| {
+            info!(log, "Changed status of leaderboard entry"; "id" => id, "status" => item.status.as_str());
+
+            HttpResponse::Ok()
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block")
+                .json(entry)
+        })
+        .map_err(log_error(log))
+}
+
+// Report the prize winners of each difficulty (as JSON or as a CSV file for the
+// prize team).
+pub async fn get_prize_winners(
+    req: HttpRequest,
+    state: web::Data<models::AppState>,
+    web::Query(query): web::Query<models::PrizeWinnersQueryRequest>,
+) -> Result<HttpResponse, CustomError> {
+    auth::check_if_admin_authorized(&req, &state.config.admin)?;
+
+    match query.validate() {
+        Ok(_) => (),
+        Err(_e) => return Err(CustomError::ValidationError),
+    }
+
+    let log = state.log.new(o!("handler" => "get_prize_winners"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_prize_winners(&client, query.count).await;
+
+    result
+        .and_then(|winners| {
+            let mut response = HttpResponse::Ok();
+            response
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block");
+
+            match query.format {
+                models::ExportFormat::Json => Ok(response.json(winners)),
+                models::ExportFormat::Csv => {
+                    let mut body = vec![];
+                    utils::write_csv(&mut body, &winners)
+                        .map_err(|err| errors::map_io_error(err.into()))?;
+
+                    Ok(response
+                        .content_type("text/csv; charset=utf-8")
+                        .header(
+                            "Content-Disposition",
+                            "attachment; filename=\"prize_winners.csv\"",
+                        )
+                        .body(body))
+                }
+            }
+        })
+        .map_err(log_error(log))
+}
+
+pub async fn update_material_value(
+    req: HttpRequest,
+    state: web::Data<models::AppState>,
+    web::Path(name): web::Path<String>,
+    item: web::Json<models::UpdateMaterialValueRequest>,
+) -> Result<impl Responder, CustomError> {
+    auth::check_if_admin_authorized(&req, &state.config.admin)?;
+
+    match item.validate() {
+        Ok(_) => (),
+        Err(_e) => return Err(CustomError::ValidationError),
+    }
+
+    let log = state.log.new(o!("handler" => "update_material_value"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::update_material_value(&client, &name, item.value).await;
+
+    result
+        .and_then(|material| material.ok_or(CustomError::NotFound))
+        .map(|material| {
+            info!(log, "Changed value of material"; "name" => &name, "value" => item.value);
+
+            HttpResponse::Ok()
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block")
+                .json(material)
+        })
+        .map_err(log_error(log))
+}
+
+pub async fn reset_material_quantity(
+    req: HttpRequest,
+    state: web::Data<models::AppState>,
+    web::Path(name): web::Path<String>,
+) -> Result<impl Responder, CustomError> {
+    auth::check_if_admin_authorized(&req, &state.config.admin)?;
+
+    let log = state.log.new(o!("handler" => "reset_material_quantity"));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::reset_material_quantity(&client, &name).await;
+
+    // Push the new material totals to the subscribers (without failing the
+    // already applied reset).
+    if let Ok(Some(_)) = result {
+        if state.material_hub.has_subscribers() {
+            if let Err(_err) = state.material_hub.refresh(&client).await {
+                warn!(log, "Error fetching the material totals to push");
+            }
+        }
+    }
+
+    result
+        .and_then(|material| material.ok_or(CustomError::NotFound))
+        .map(|material| {
+            info!(log, "Reset quantity of material"; "name" => &name);
+
+            HttpResponse::Ok()
+                .header("Content-Security-Policy", "default-src 'self'")
+                .header("Strict-Transport-Security", "max-age=3600")
+                .header("X-XSS-Protection", "1; mode=block")
+                .json(material)
+        })
+        .map_err(log_error(log))
+}
+
 pub async fn vsauce_handler() -> Result<impl Responder, std::io::Error> {
     Ok(HttpResponse::Ok()
         .header("Content-Security-Policy", "default-src 'self'")
@@ -162,10 +664,8 @@ pub async fn vsauce_handler() -> Result<impl Responder, std::io::Error> {
 }
 
 pub async fn fortune_cookie_handler() -> Result<impl Responder, std::io::Error> {
-    let fortune: String;
-
-    if cfg!(target_os = "windows") {
-        fortune = "No fortune cookies on Windows. 😔".to_string();
+    let fortune = if cfg!(target_os = "windows") {
+        "No fortune cookies on Windows. 😔".to_string()
     } else {
         // Need to run `sudo apt install fortune` first since this command is not
         // built-in on Ubuntu.
@@ -180,7 +680,7 @@ pub async fn fortune_cookie_handler() -> Result<impl Responder, std::io::Error>
                 )),
             });
 
-        fortune = match output {
+        match output {
             Ok(_) => std::str::from_utf8(&output.unwrap().stdout)
                 .unwrap()
                 .to_string(),
@@ -188,7 +688,7 @@ pub async fn fortune_cookie_handler() -> Result<impl Responder, std::io::Error>
                         from giving a fortune cookie to you. Apologies! 😔"
                 .to_string(),
         }
-    }
+    };
 
     Ok(HttpResponse::Ok()
         .header("Content-Security-Policy", "default-src 'self'")
@@ -197,6 +697,46 @@ pub async fn fortune_cookie_handler() -> Result<impl Responder, std::io::Error>
         .body(fortune))
 }
 
+// Permanently redirect any plain-HTTP request to the same host and path over
+// HTTPS. The target host is taken from the `Host` header only (never from
+// `X-Forwarded-Host`) and must be one of the allowed hosts, so that the
+// redirect cannot be pointed at an arbitrary host.
+pub async fn redirect_to_https(
+    req: HttpRequest,
+    state: web::Data<models::RedirectState>,
+) -> Result<HttpResponse, CustomError> {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(utils::strip_port)
+        .unwrap_or("");
+
+    if host.is_empty() || !state.allowed_hosts.matches(host) {
+        return Err(CustomError::BadRequest);
+    }
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    let location = match state.https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+
+    // A 301 may turn other methods into GET, so only use it where that cannot
+    // happen and preserve the method (and body) with a 308 otherwise.
+    let mut response = match *req.method() {
+        Method::GET | Method::HEAD => HttpResponse::MovedPermanently(),
+        _ => HttpResponse::PermanentRedirect(),
+    };
+
+    Ok(response.header(header::LOCATION, location).finish())
+}
+
 pub async fn favicon_handler() -> Result<fs::NamedFile, std::io::Error> {
     // Development mode.
     #[cfg(debug_assertions)]
@@ -1,42 +1,74 @@
 // <main>
 use actix_cors::Cors;
+use actix_files as fs;
 use actix_ratelimit::{MemoryStore, MemoryStoreActor, RateLimiter};
-use actix_web::{guard, http, middleware, web, App, FromRequest, HttpServer};
+use actix_web::{http, middleware, web, App, FromRequest, HttpServer};
 use actix_web_middleware_redirect_https::RedirectHTTPS;
+use clap::Parser;
 use dotenv::dotenv;
-use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
-use slog::{slog_info, Level};
-use std::time::Duration;
+use futures::future::try_join;
+use slog::{slog_info, Level, Logger};
+use std::{sync::Arc, time::Duration};
 
+mod auth;
+mod cli;
 mod config;
 mod constants;
 mod db;
 mod defaults;
 mod errors;
+mod guards;
 mod handlers;
 mod logging;
+mod migrations;
 mod models;
+mod plausibility;
+mod realtime;
+mod sessions;
+mod shutdown;
+mod tls;
 mod utils;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> Result<(), errors::StartupError> {
     dotenv().ok();
 
-    let config = config::Config::from_env().expect("error getting configuration from environment");
-
-    let pool = config.configure_pool();
-
-    // Initialize store.
-    let store = MemoryStore::new();
+    let cli = cli::Cli::parse();
 
     // Set environment variables for logging (consider using slog_envlogger: https://crates.io/crates/slog-envlogger).
     // Might need https://crates.io/crates/slog-scope and https://crates.io/crates/slog-stdlog as additional dependencies.
     // std::env::set_var("RUST_LOG", "actix_web=info,actix_server=info");
     // std::env::set_var("RUST_BACKTRACE", "full");
 
-    let logger = logging::configure_log();
+    // The guard flushes the pending log records once `main` returns.
+    let (logger, _log_guard) = logging::configure_log();
     logging::set_global_level(Level::Trace);
 
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve(logger).await,
+        cli::Command::Task(task) => cli::run(task, &logger).await,
+    }
+}
+
+async fn serve(logger: Logger) -> Result<(), errors::StartupError> {
+    let config = Arc::new(config::Config::from_env()?);
+    config.validate_serve()?;
+
+    let pool = config.configure_pool()?;
+
+    // Initialize store.
+    let store = MemoryStore::new();
+
+    if config.server.auto_migrate {
+        migrations::run_migrations(&pool, &logger).await?;
+    }
+
+    // Check the time zone of the leaderboard periods with the database before
+    // accepting any requests.
+    let client = pool.get().await?;
+    db::check_time_zone(&client, &config.leaderboard.time_zone).await?;
+    drop(client);
+
     slog_info!(
         logger,
         "Starting server at https://{}:{}/",
@@ -44,33 +76,36 @@ async fn main() -> std::io::Result<()> {
         config.server.port
     );
 
-    let mut builder: SslAcceptorBuilder;
-
-    // Development mode.
-    if cfg!(debug_assertions) {
-        builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        builder
-            .set_private_key_file("tls/privkey.pem", SslFiletype::PEM)
-            .unwrap();
-        builder.set_certificate_chain_file("tls/cert.pem").unwrap();
-    }
-    // Production mode.
-    else {
-        builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        builder
-            .set_private_key_file("/root/overdue_backend/tls/privkey.pem", SslFiletype::PEM)
-            .unwrap();
-        builder
-            .set_certificate_chain_file("/root/overdue_backend/tls/fullchain.pem")
-            .unwrap();
+    if let Some(http_port) = config.server.http_port {
+        slog_info!(
+            logger,
+            "Starting redirect server at http://{}:{}/",
+            config.server.host,
+            http_port
+        );
     }
 
-    HttpServer::new(move || {
+    let certificates = tls::CertificateStore::new(&config.tls)?;
+    let builder = certificates.configure_acceptor()?;
+
+    tls::spawn_reloader(certificates, config.tls.reload_interval, logger.clone());
+
+    let server_config = config.clone();
+    let server_pool = pool.clone();
+    let server_logger = logger.clone();
+    let leaderboard_hub = Arc::new(realtime::LeaderboardHub::default());
+    let material_hub = Arc::new(realtime::MaterialHub::default());
+    let server = HttpServer::new(move || {
+        let config = server_config.clone();
+        let score_payload_limit = config.server.score_payload_limit;
+        let allowed_hosts = guards::AllowedHosts::new(&config.server.allowed_hosts);
+
         // Define Cross-Origin Resource Sharing policy.
-        let cors = Cors::default()
-            .allowed_origin(constants::GAME_CLIENT_URL_DOMAIN_ORIGIN)
-            .allowed_origin(constants::PUBLIC_FACING_GAME_CLIENT_URL)
-            .allowed_origin(constants::FRONT_DOMAIN)
+        let cors = config
+            .cors
+            .allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
             .allowed_methods(vec!["GET", "POST"])
             .allowed_headers(vec![
                 http::header::CONTENT_TYPE,
@@ -90,8 +125,9 @@ async fn main() -> std::io::Result<()> {
                 http::header::STRICT_TRANSPORT_SECURITY,
                 http::header::CONTENT_SECURITY_POLICY,
                 http::header::X_XSS_PROTECTION,
+                http::header::HeaderName::from_static(constants::IDEMPOTENCY_KEY_HEADER),
             ])
-            .max_age(constants::CORS_MAX_AGE_DURATION);
+            .max_age(config.cors.max_age);
 
         // Use this permissive policy for debugging phase/development mode.
         // let cors = Cors::permissive();
@@ -103,67 +139,186 @@ async fn main() -> std::io::Result<()> {
             .wrap(cors)
             // Redirect HTTP to HTTPS
             .wrap(RedirectHTTPS::default())
-            // Register the middleware which allows for a maximum of 60 requests per minute per client based on IP address.
+            // Register the middleware which allows for a maximum of 60 requests per minute (by default) per client based on IP address.
             .wrap(
                 RateLimiter::new(
                 MemoryStoreActor::from(store.clone()).start())
-                    .with_interval(Duration::from_secs(constants::RATE_LIMIT_INTERVAL_DURATION))
-                    .with_max_requests(constants::RATE_LIMIT_MAX_REQUESTS)
+                    .with_interval(Duration::from_secs(config.rate_limit.interval))
+                    .with_max_requests(config.rate_limit.max_requests)
             )
             .data(models::AppState {
-                pool: pool.clone(),
-                log: logger.clone()
+                pool: server_pool.clone(),
+                log: server_logger.clone(),
+                config: config.clone(),
+                leaderboard_hub: leaderboard_hub.clone(),
+                material_hub: material_hub.clone()
             })
             // Define all of the available endpoints.
             .service(
                 web::resource("/submit_score{_:/?}")
-                .data(web::JsonConfig::default().limit(constants::INCOMING_SCORE_PAYLOAD_LIMIT))
-                .guard(guard::Host(constants::SERVER_HOST_URL))
+                .data(web::JsonConfig::default().limit(score_payload_limit))
+                .guard(allowed_hosts.clone())
                 .route(web::post().to(handlers::submit_score)))
                 .app_data(
                     web::Json::<models::ScoreEntry>::configure(|cfg| {
                         // Limit size of the payload.
-                        cfg.limit(constants::INCOMING_SCORE_PAYLOAD_LIMIT)
+                        cfg.limit(score_payload_limit)
+                           .error_handler(errors::json_error_handler)
+                    })
+                )
+            .service(
+                web::resource("/start_session{_:/?}")
+                .guard(allowed_hosts.clone())
+                .route(web::post().to(handlers::start_session)))
+                .app_data(
+                    web::Json::<models::StartSessionRequest>::configure(|cfg| {
+                        cfg.limit(score_payload_limit)
                            .error_handler(errors::json_error_handler)
                     })
                 )
             .service(
                 web::resource("/get_leaderboard{_:/?}")
-                .guard(guard::Host(constants::SERVER_HOST_URL))
+                .guard(allowed_hosts.clone())
                 .route(web::get().to(handlers::get_leaderboard)))
                 .app_data(
                     web::Query::<models::LeaderboardQueryRequest>::configure(|cfg| {
                         cfg.error_handler(errors::query_error_handler)
                     })
                 )
+            .service(
+                web::resource("/leaderboard/around{_:/?}")
+                .guard(allowed_hosts.clone())
+                .route(web::get().to(handlers::get_leaderboard_around)))
+                .app_data(
+                    web::Query::<models::LeaderboardAroundQueryRequest>::configure(|cfg| {
+                        cfg.error_handler(errors::query_error_handler)
+                    })
+                )
+            .service(
+                web::resource("/leaderboard/live{_:/?}")
+                .guard(allowed_hosts.clone())
+                .route(web::get().to(handlers::get_live_leaderboard)))
             .service(
                 web::resource("/get_materials{_:/?}")
-                .guard(guard::Host(constants::SERVER_HOST_URL))
+                .guard(allowed_hosts.clone())
                 .route(web::get().to(handlers::get_materials)))
+            .service(
+                web::resource("/get_goals{_:/?}")
+                .guard(allowed_hosts.clone())
+                .route(web::get().to(handlers::get_goals)))
+            .service(
+                web::resource("/materials/stream{_:/?}")
+                .guard(allowed_hosts.clone())
+                .route(web::get().to(handlers::get_materials_stream)))
+            // Define the admin endpoints (which require an admin bearer token).
+            .service(
+                web::scope("/admin")
+                .guard(allowed_hosts.clone())
+                .route("/entries", web::get().to(handlers::list_entries))
+                .route("/entries/{id}", web::delete().to(handlers::delete_entry))
+                .route("/entries/{id}/status", web::post().to(handlers::update_entry_status))
+                .route("/prize_winners", web::get().to(handlers::get_prize_winners))
+                .route("/materials/{name}", web::put().to(handlers::update_material_value))
+                .route("/materials/{name}/reset", web::post().to(handlers::reset_material_quantity)))
+                .app_data(
+                    web::Query::<models::AdminEntriesQueryRequest>::configure(|cfg| {
+                        cfg.error_handler(errors::query_error_handler)
+                    })
+                )
+                .app_data(
+                    web::Query::<models::PrizeWinnersQueryRequest>::configure(|cfg| {
+                        cfg.error_handler(errors::query_error_handler)
+                    })
+                )
+                .app_data(
+                    web::Json::<models::UpdateEntryStatusRequest>::configure(|cfg| {
+                        cfg.error_handler(errors::json_error_handler)
+                    })
+                )
+                .app_data(
+                    web::Json::<models::UpdateMaterialValueRequest>::configure(|cfg| {
+                        cfg.error_handler(errors::json_error_handler)
+                    })
+                )
             // Define easter egg endpoints.
             .service(
                 web::resource("/vsauce{_:/?}")
-                .guard(guard::Host(constants::SERVER_HOST_URL))
+                .guard(allowed_hosts.clone())
                 .route(web::get().to(handlers::vsauce_handler)))
             .service(
                 web::resource("/fortune{_:/?}")
-                .guard(guard::Host(constants::SERVER_HOST_URL))
+                .guard(allowed_hosts.clone())
                 .route(web::get().to(handlers::fortune_cookie_handler)))
             // Serve favicon image.
             .service(
                 web::resource("/favicon.ico")
-                .guard(guard::Host(constants::SERVER_HOST_URL))
+                .guard(allowed_hosts.clone())
                 .route(web::get().to(handlers::favicon_handler)))
             // Default 404 handler.
             .default_service(web::route().to(handlers::default_handler))
     })
-    .keep_alive(constants::KEEP_ALIVE_DURATION)
+    .keep_alive(config.server.keep_alive)
+    .shutdown_timeout(config.server.shutdown_timeout)
+    .disable_signals()
     .bind_openssl(
         format!("{}:{}", config.server.host, config.server.port),
         builder,
     )?
-    .run()
-    .await
+    .run();
+
+    // Optionally listen on plain HTTP as well, but only to redirect to HTTPS and
+    // to answer ACME HTTP-01 challenges (which must be served over plain HTTP).
+    match config.server.http_port {
+        Some(http_port) => {
+            let redirect_state = models::RedirectState {
+                https_port: config.server.port,
+                allowed_hosts: guards::AllowedHosts::new(&config.server.allowed_hosts),
+            };
+            let acme_challenge_dir = config.server.acme_challenge_dir.clone();
+
+            let redirect_server = HttpServer::new(move || {
+                let allowed_hosts = redirect_state.allowed_hosts.clone();
+                let app = App::new().data(redirect_state.clone());
+
+                let app = match &acme_challenge_dir {
+                    Some(dir) => app.service(
+                        web::scope("/.well-known/acme-challenge")
+                        .guard(allowed_hosts.clone())
+                        .service(fs::Files::new("", dir))),
+                    None => app,
+                };
+
+                app.service(
+                    web::resource("/{_:.*}")
+                    .guard(allowed_hosts)
+                    .to(handlers::redirect_to_https))
+                .default_service(web::route().to(handlers::default_handler))
+            })
+            .keep_alive(config.server.keep_alive)
+            .shutdown_timeout(config.server.shutdown_timeout)
+            .disable_signals()
+            .bind(format!("{}:{}", config.server.host, http_port))?
+            .run();
+
+            shutdown::spawn_signal_handler(
+                vec![server.clone(), redirect_server.clone()],
+                logger.clone(),
+            )?;
+
+            try_join(server, redirect_server).await?;
+        }
+        None => {
+            shutdown::spawn_signal_handler(vec![server.clone()], logger.clone())?;
+
+            server.await?
+        }
+    }
+
+    // All of the workers (and their clones of the pool) are gone at this point.
+    let closed = shutdown::close_pool(&pool).await;
+    slog_info!(logger, "Server stopped"; "closed_connections" => closed);
+
+    Ok(())
 }
 // </main>
 
@@ -0,0 +1,137 @@
+// Define the versioned database schema migrations. The migration scripts are
+// compiled into the binary and are applied in order, each in its own
+// transaction, with the applied versions recorded in `schema_migrations`.
+use deadpool_postgres::Pool;
+use slog::{info, o, Logger};
+
+use crate::errors::StartupError;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+// Append new migrations at the end of this list (never edit or reorder migrations
+// which might have already been applied to a production database).
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "idempotency_keys",
+        sql: include_str!("../migrations/0002_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "session_nonces",
+        sql: include_str!("../migrations/0003_session_nonces.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "plausibility_verdicts",
+        sql: include_str!("../migrations/0004_plausibility_verdicts.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "moderation_status",
+        sql: include_str!("../migrations/0005_moderation_status.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "community_goals",
+        sql: include_str!("../migrations/0006_community_goals.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "material_adjustments",
+        sql: include_str!("../migrations/0007_material_adjustments.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "scoped_idempotency_keys",
+        sql: include_str!("../migrations/0008_scoped_idempotency_keys.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "relax_score_and_quantity_checks",
+        sql: include_str!("../migrations/0009_relax_score_and_quantity_checks.sql"),
+    },
+];
+
+// Arbitrary key of the advisory lock which prevents multiple server instances
+// from applying the same migration concurrently.
+const MIGRATION_LOCK_KEY: i64 = 0x006f_7665_7264_7565;
+
+// Apply all pending migrations and return the versions that have been applied.
+pub async fn run_migrations(pool: &Pool, log: &Logger) -> Result<Vec<i32>, StartupError> {
+    let log = log.new(o!("task" => "migrations"));
+    let mut client = pool.get().await?;
+
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (version INT PRIMARY KEY, name TEXT \
+             NOT NULL, applied_at TIMESTAMPTZ NOT NULL DEFAULT now())",
+        )
+        .await?;
+
+    let mut applied = vec![];
+
+    for migration in MIGRATIONS.iter() {
+        let transaction = client.transaction().await?;
+
+        transaction
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])
+            .await?;
+
+        let already_applied = transaction
+            .query_opt(
+                "SELECT version FROM schema_migrations WHERE version = $1",
+                &[&migration.version],
+            )
+            .await?
+            .is_some();
+
+        if already_applied {
+            transaction.rollback().await?;
+            continue;
+        }
+
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        transaction.commit().await?;
+
+        info!(
+            log,
+            "Applied migration {} ({})", migration.version, migration.name
+        );
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+// Define unit tests for the migration list.
+#[cfg(test)]
+mod tests {
+    use super::MIGRATIONS;
+
+    #[test]
+    fn test_migration_versions_are_strictly_increasing() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(
+                migration.version,
+                index as i32 + 1,
+                "Migration versions should start at 1 and have no gaps"
+            );
+        }
+    }
+}
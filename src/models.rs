@@ -1,11 +1,19 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ArgEnum;
 use deadpool_postgres::Pool;
 use postgres_types::{FromSql, ToSql};
 use serde::{Deserialize, Serialize};
 use slog::Logger;
+use std::sync::Arc;
 use tokio_pg_mapper_derive::PostgresMapper;
 use validator::Validate;
 
-use crate::{constants, defaults};
+use crate::{
+    config::Config,
+    constants, defaults,
+    guards::AllowedHosts,
+    realtime::{LeaderboardHub, MaterialHub},
+};
 
 #[derive(Debug, Validate, Serialize, Deserialize, Clone, PostgresMapper)]
 #[pg_mapper(table = "material")]
@@ -14,11 +22,12 @@ pub struct MaterialValueEntry {
     pub value: i32,
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize, PostgresMapper, ToSql, FromSql, Clone)]
+#[derive(
+    Debug, Validate, Serialize, Deserialize, PostgresMapper, ToSql, FromSql, Clone, PartialEq,
+)]
 #[pg_mapper(table = "material")]
 pub struct MaterialEntry {
     pub name: String,
-    #[validate(range(min = "constants::MIN_MATERIALS", max = "constants::MAX_MATERIALS"))]
     pub quantity: i32,
 }
 
@@ -35,38 +44,126 @@ pub struct ScoreEntry {
     pub email: String,
     #[validate(regex = "constants::DIFFICULTY_REGEX")]
     pub difficulty: String,
-    #[validate(range(min = "constants::MIN_SCORE", max = "constants::MAX_SCORE"))]
     pub score: i32,
-    #[validate(range(min = "constants::MIN_BONUS_VALUE", max = "constants::MAX_BONUS_VALUE"))]
     #[serde(default = "defaults::default_bonus")]
     pub bonus: i32,
     pub materials: Vec<MaterialEntry>,
+    // Token issued by `/start_session` when the game was started.
+    #[serde(default)]
+    pub session_token: Option<String>,
+}
+
+#[derive(Debug, Validate, Serialize, Deserialize)]
+pub struct StartSessionRequest {
+    #[validate(regex = "constants::DIFFICULTY_REGEX")]
+    pub difficulty: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StartSessionResponse {
+    pub session_token: String,
+    // Unix timestamp (in seconds) after which the token can no longer be used.
+    pub expires_at: i64,
 }
 
 #[derive(Debug, Validate, Serialize, Deserialize)]
 // #[serde(rename_all = "camelCase")]
 pub struct LeaderboardQueryRequest {
-    #[validate(range(
-        min = "constants::MIN_LEADERBOARD_LENGTH",
-        max = "constants::MAX_LEADERBOARD_LENGTH"
-    ))]
     #[serde(default = "defaults::default_max_entries")]
     pub max_entries: i64,
     #[validate(regex = "constants::DIFFICULTY_REGEX")]
     #[serde(default = "defaults::default_difficulty")]
     pub difficulty: String,
+    // Either the number of entries to skip or the `next_cursor` of the previous
+    // page (giving any of them returns a `LeaderboardPageResponse` instead of a
+    // bare array).
+    #[validate(range(min = 0))]
+    pub offset: Option<i64>,
+    pub cursor: Option<String>,
+    // Overrides `LEADERBOARD__BEST_PER_PLAYER`.
+    pub best_per_player: Option<bool>,
+    // Only list the entries submitted within this period (all-time by default).
+    // The `range` period goes from the start of the `from` date to the end of
+    // the `to` date (either of which can be left open).
+    pub period: Option<LeaderboardPeriod>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+// Periods of the leaderboard (in the time zone of `LEADERBOARD__TIME_ZONE`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LeaderboardPeriod {
+    AllTime,
+    Today,
+    // Starting on Monday.
+    ThisWeek,
+    Range,
+}
+
+impl LeaderboardPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AllTime => "all-time",
+            Self::Today => "today",
+            Self::ThisWeek => "this-week",
+            Self::Range => "range",
+        }
+    }
+}
+
+#[derive(Debug, Validate, Serialize, Deserialize)]
+pub struct LeaderboardAroundQueryRequest {
+    pub id: i32,
+    // Number of entries to return above and below the entry.
+    #[validate(range(min = 1, max = 25))]
+    #[serde(default = "defaults::default_around_count")]
+    pub count: i64,
+    // Overrides `LEADERBOARD__BEST_PER_PLAYER`.
+    pub best_per_player: Option<bool>,
+}
+
+// Model for an entry together with its neighbors on the leaderboard of its
+// difficulty (`above` is ordered by ascending rank, like the leaderboard).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderboardAroundResponse {
+    pub above: Vec<LeaderboardMultipleEntries>,
+    pub entry: LeaderboardSingleEntry,
+    pub below: Vec<LeaderboardMultipleEntries>,
+}
+
+// Selection of the leaderboard entries to be listed, starting after the entry
+// with the given score, submission time and ID (if any), skipping `offset`
+// entries. Only the
+// entries submitted within `since` (inclusive) and `until` (exclusive) are
+// ranked.
+pub struct LeaderboardPage {
+    pub limit: i64,
+    pub offset: i64,
+    pub after: Option<(i32, DateTime<Utc>, i32)>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderboardPageResponse {
+    pub entries: Vec<LeaderboardMultipleEntries>,
+    // Cursor of the next page, or `null` if this is the last page.
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Validate, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "leaderboard")]
 pub struct LeaderboardSingleEntry {
+    // ID of the entry (e.g., to look up its neighbors via `/leaderboard/around`).
+    pub id: i32,
     #[validate(length(equal = 3), regex = "constants::NAME_REGEX")]
     pub name: String,
     #[validate(length(equal = 1), regex = "constants::GENDER_REGEX")]
     pub gender: String,
     #[validate(regex = "constants::DIFFICULTY_REGEX")]
     pub difficulty: String,
-    #[validate(range(min = "constants::MIN_SCORE", max = "constants::MAX_SCORE"))]
+    #[validate(range(min = 0))]
     pub score: i32,
     #[validate(range(min = 1))]
     pub rank: i64,
@@ -75,14 +172,204 @@ pub struct LeaderboardSingleEntry {
 #[derive(Debug, Validate, Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "leaderboard")]
 pub struct LeaderboardMultipleEntries {
+    // Only used to build the cursors of the leaderboard pages.
+    #[serde(skip)]
+    pub id: i32,
     #[validate(length(equal = 3), regex = "constants::NAME_REGEX")]
     pub name: String,
     #[validate(length(equal = 1), regex = "constants::GENDER_REGEX")]
     pub gender: String,
-    #[validate(range(min = "constants::MIN_SCORE", max = "constants::MAX_SCORE"))]
+    #[validate(range(min = 0))]
     pub score: i32,
     #[validate(range(min = 1))]
     pub rank: i64,
+    // Likewise only used to build the cursors (but never missing).
+    #[serde(skip)]
+    pub creation_date: Option<DateTime<Utc>>,
+}
+
+// Definition of a community goal (as seeded from a JSON file), which is unlocked
+// once the given quantity of each of its materials has been collected.
+#[derive(Debug, Deserialize)]
+pub struct GoalDefinition {
+    pub name: String,
+    pub difficulty: String,
+    pub materials: Vec<MaterialEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GoalMaterialProgress {
+    pub name: String,
+    pub quantity: i32,
+    pub required: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GoalProgress {
+    pub name: String,
+    pub difficulty: String,
+    // Percentage of the required materials which have been collected (where
+    // surplus quantities of a material do not count towards the others).
+    pub progress: f64,
+    // Time at which the goal has first been reached (if it has been).
+    pub unlocked_at: Option<DateTime<Utc>>,
+    pub materials: Vec<GoalMaterialProgress>,
+}
+
+// Request sent by the WebSocket clients of `/leaderboard/live` to choose the
+// difficulties whose top entries are pushed to them.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum LeaderboardLiveRequest {
+    Subscribe { difficulties: Vec<String> },
+    Unsubscribe { difficulties: Vec<String> },
+}
+
+// Top entry pushed to the WebSocket clients (including its ID, so that diffs can
+// refer to it).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeaderboardLiveEntry {
+    pub id: i32,
+    pub name: String,
+    pub gender: String,
+    pub score: i32,
+    pub rank: i64,
+}
+
+impl From<LeaderboardMultipleEntries> for LeaderboardLiveEntry {
+    fn from(entry: LeaderboardMultipleEntries) -> Self {
+        Self {
+            id: entry.id,
+            name: entry.name,
+            gender: entry.gender,
+            score: entry.score,
+            rank: entry.rank,
+        }
+    }
+}
+
+// Message pushed to the WebSocket clients of `/leaderboard/live`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LeaderboardLiveUpdate {
+    // All top entries of a difficulty (sent after subscribing to it).
+    Snapshot {
+        difficulty: String,
+        entries: Vec<LeaderboardLiveEntry>,
+    },
+    // New or changed top entries and the IDs of the entries which have dropped
+    // out of the top.
+    Diff {
+        difficulty: String,
+        entries: Vec<LeaderboardLiveEntry>,
+        removed: Vec<i32>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+// Moderation status of a leaderboard entry. Removed entries are never shown on
+// the leaderboard, while flagged entries are only hidden if
+// `LEADERBOARD__HIDE_FLAGGED` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryStatus {
+    Pending,
+    Approved,
+    Flagged,
+    Removed,
+}
+
+impl EntryStatus {
+    // Value stored in the `status` column of the leaderboard table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Flagged => "flagged",
+            Self::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, Validate, Serialize, Deserialize)]
+pub struct UpdateEntryStatusRequest {
+    pub status: EntryStatus,
+    #[validate(length(min = 1, max = 255))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Validate, Serialize, Deserialize)]
+pub struct AdminEntriesQueryRequest {
+    #[validate(regex = "constants::DIFFICULTY_REGEX")]
+    pub difficulty: Option<String>,
+}
+
+#[derive(Debug, Validate, Serialize, Deserialize)]
+pub struct UpdateMaterialValueRequest {
+    // Same range as the check constraint of the `value` column.
+    #[validate(range(min = 1, max = 1000))]
+    pub value: i32,
+}
+
+// Output formats of the leaderboard export and the prize winner report.
+#[derive(Debug, ArgEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Validate, Serialize, Deserialize)]
+pub struct PrizeWinnersQueryRequest {
+    #[validate(range(min = 1, max = 100))]
+    #[serde(default = "defaults::default_prize_winners_count")]
+    pub count: i64,
+    #[serde(default = "defaults::default_export_format")]
+    pub format: ExportFormat,
+}
+
+// Model for the best entry of a player (identified by email) among the prize
+// winners of a difficulty.
+#[derive(Debug, Serialize)]
+pub struct PrizeWinnerEntry {
+    pub difficulty: String,
+    pub place: i64,
+    pub id: i32,
+    pub name: String,
+    pub gender: String,
+    pub email: String,
+    pub score: i32,
+    pub creation_date: DateTime<Utc>,
+    pub status: String,
+}
+
+// Model for leaderboard exports and the admin API (this includes the players'
+// emails, so it must never be returned by any of the public endpoints).
+#[derive(Debug, Serialize)]
+pub struct LeaderboardExportEntry {
+    pub id: i32,
+    pub name: String,
+    pub gender: String,
+    pub email: String,
+    pub difficulty: String,
+    pub score: i32,
+    pub materials: Vec<MaterialEntry>,
+    pub creation_date: DateTime<Utc>,
+    // Verdict of the plausibility model (see `plausibility::Verdict`).
+    pub verdict: String,
+    pub status: String,
+    pub status_reason: Option<String>,
+}
+
+// Model for a material whose collected quantity does not match the total
+// recomputed from the leaderboard entries.
+#[derive(Debug, Serialize)]
+pub struct MaterialDrift {
+    pub name: String,
+    pub recorded: i32,
+    pub expected: i64,
 }
 
 // Model for error message.
@@ -93,9 +380,20 @@ pub struct ErrorResponse {
     pub message: String,
 }
 
+// Model for the plain-HTTP redirect listener.
+#[derive(Clone)]
+pub struct RedirectState {
+    pub https_port: u32,
+    pub allowed_hosts: AllowedHosts,
+}
+
 // Model for logging.
 #[derive(Clone)]
 pub struct AppState {
     pub pool: Pool,
     pub log: Logger,
+    pub config: Arc<Config>,
+    // Shared by all workers (see `realtime`).
+    pub leaderboard_hub: Arc<LeaderboardHub>,
+    pub material_hub: Arc<MaterialHub>,
 }
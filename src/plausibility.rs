@@ -0,0 +1,94 @@
+// Define the plausibility model which judges whether a score could have been
+// achieved within the duration of the play session (derived from the start
+// time of the session token).
+use crate::{config::PlausibilityConfig, models::ScoreEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Plausible,
+    Implausible,
+}
+
+impl Verdict {
+    // Value stored in the `verdict` column of the leaderboard table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Plausible => "plausible",
+            Self::Implausible => "implausible",
+        }
+    }
+}
+
+// Judge the entry based on the number of seconds the session has lasted.
+pub fn assess_entry(item: &ScoreEntry, elapsed: i64, config: &PlausibilityConfig) -> Verdict {
+    let limits = config.limits(&item.difficulty);
+    let elapsed = elapsed.max(0) as f64;
+
+    let materials: i32 = item
+        .materials
+        .iter()
+        .map(|material| material.quantity)
+        .sum();
+
+    if f64::from(item.score) > elapsed * limits.max_score_per_second
+        || f64::from(materials) > elapsed / 60.0 * limits.max_materials_per_minute
+    {
+        Verdict::Implausible
+    } else {
+        Verdict::Plausible
+    }
+}
+
+// Define unit tests for the plausibility model.
+#[cfg(test)]
+mod tests {
+    use super::{assess_entry, Verdict};
+    use crate::{
+        config::{DifficultyLimits, PlausibilityConfig},
+        models::{MaterialEntry, ScoreEntry},
+    };
+
+    fn entry(difficulty: &str, score: i32, quantity: i32) -> ScoreEntry {
+        ScoreEntry {
+            name: "JRT".to_string(),
+            gender: "m".to_string(),
+            email: "james_raphael@mymail.sutd.edu.sg".to_string(),
+            difficulty: difficulty.to_string(),
+            score,
+            bonus: 0,
+            materials: vec![MaterialEntry {
+                name: "portalGun".to_string(),
+                quantity,
+            }],
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn test_assess_entry_per_difficulty() {
+        let config = PlausibilityConfig {
+            hard: DifficultyLimits {
+                max_score_per_second: 2.0,
+                max_materials_per_minute: 6.0,
+            },
+            ..PlausibilityConfig::default()
+        };
+
+        assert_eq!(
+            assess_entry(&entry("hard", 120, 6), 60, &config),
+            Verdict::Plausible
+        );
+        assert_eq!(
+            assess_entry(&entry("hard", 121, 6), 60, &config),
+            Verdict::Implausible
+        );
+        assert_eq!(
+            assess_entry(&entry("hard", 120, 7), 60, &config),
+            Verdict::Implausible
+        );
+        assert_eq!(
+            assess_entry(&entry("easy", 121, 7), 60, &config),
+            Verdict::Plausible
+        );
+    }
+}
@@ -0,0 +1,604 @@
+// Define the real-time updates pushed to the clients. WebSocket clients of
+// `/leaderboard/live` subscribe to difficulties and receive a snapshot of their
+// top entries, followed by the differences whenever a submission changes them.
+// Clients of `/materials/stream` receive the material totals as server-sent
+// events whenever a submission adds to them. The hubs remember the last pushed
+// state and are shared by all of the server workers.
+use actix::{
+    prelude::SendError, Actor, ActorContext, ActorFuture, AsyncContext, Handler, Message,
+    Recipient, StreamHandler, WrapFuture,
+};
+use actix_rt::time::delay_for;
+use actix_web::web::Bytes;
+use actix_web_actors::ws;
+use deadpool_postgres::Client;
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    lock::Mutex as AsyncMutex,
+    stream, Stream, StreamExt,
+};
+use slog::warn;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::Config,
+    constants, db,
+    errors::CustomError,
+    models::{
+        AppState, LeaderboardLiveEntry, LeaderboardLiveRequest, LeaderboardLiveUpdate,
+        LeaderboardPage, MaterialEntry,
+    },
+};
+
+// Sockets are pinged at this interval and closed once they have not answered
+// for longer than the timeout.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Interval of the comments which keep idle event streams (and any proxies in
+// between) from timing out.
+const EVENT_STREAM_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+// JSON message pushed to a socket.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Push(pub String);
+
+struct Subscriber {
+    difficulties: Vec<String>,
+    recipient: Recipient<Push>,
+}
+
+#[derive(Default)]
+pub struct LeaderboardHub {
+    next_id: AtomicUsize,
+    subscribers: Mutex<HashMap<usize, Subscriber>>,
+    boards: Mutex<HashMap<String, Vec<LeaderboardLiveEntry>>>,
+    refreshes: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl LeaderboardHub {
+    // Register a socket (without any subscriptions yet) and return its ID.
+    pub fn connect(&self, recipient: Recipient<Push>) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.subscribers.lock().unwrap().insert(
+            id,
+            Subscriber {
+                difficulties: vec![],
+                recipient,
+            },
+        );
+
+        id
+    }
+
+    pub fn disconnect(&self, id: usize) {
+        self.subscribers.lock().unwrap().remove(&id);
+    }
+
+    pub fn subscribe(&self, id: usize, difficulties: &[String]) {
+        if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(&id) {
+            for difficulty in difficulties {
+                if !subscriber.difficulties.contains(difficulty) {
+                    subscriber.difficulties.push(difficulty.clone());
+                }
+            }
+        }
+    }
+
+    pub fn unsubscribe(&self, id: usize, difficulties: &[String]) {
+        if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(&id) {
+            subscriber
+                .difficulties
+                .retain(|difficulty| !difficulties.contains(difficulty));
+        }
+    }
+
+    pub fn has_subscribers(&self, difficulty: &str) -> bool {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .values()
+            .any(|subscriber| subscriber.difficulties.iter().any(|d| d == difficulty))
+    }
+
+    // Fetch the current top entries of the difficulty, publish them and return
+    // them. Fetching and publishing are serialized per difficulty, since a board
+    // fetched earlier could otherwise be published after a newer one and leave
+    // the remembered board (and the following differences) out of date.
+    pub async fn refresh(
+        &self,
+        client: &Client,
+        difficulty: &str,
+        config: &Config,
+    ) -> Result<Vec<LeaderboardLiveEntry>, CustomError> {
+        let refresh = self
+            .refreshes
+            .lock()
+            .unwrap()
+            .entry(difficulty.to_string())
+            .or_default()
+            .clone();
+        let _guard = refresh.lock().await;
+
+        let board = fetch_board(client, difficulty, config).await?;
+        self.publish(difficulty, board.clone());
+
+        Ok(board)
+    }
+
+    // Remember the new top entries of the difficulty and push their differences
+    // (if any) to its subscribers.
+    pub fn publish(&self, difficulty: &str, board: Vec<LeaderboardLiveEntry>) {
+        let (entries, removed) = {
+            let mut boards = self.boards.lock().unwrap();
+            let diff = diff_boards(
+                boards
+                    .get(difficulty)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+                &board,
+            );
+            boards.insert(difficulty.to_string(), board);
+            diff
+        };
+
+        if entries.is_empty() && removed.is_empty() {
+            return;
+        }
+
+        let message = match serde_json::to_string(&LeaderboardLiveUpdate::Diff {
+            difficulty: difficulty.to_string(),
+            entries,
+            removed,
+        }) {
+            Ok(message) => message,
+            Err(_err) => return,
+        };
+
+        // Forget the sockets which have been closed in the meantime.
+        self.subscribers.lock().unwrap().retain(|_, subscriber| {
+            !subscriber.difficulties.iter().any(|d| d == difficulty)
+                || !matches!(
+                    subscriber.recipient.do_send(Push(message.clone())),
+                    Err(SendError::Closed(_))
+                )
+        });
+    }
+}
+
+// Return the entries of `board` which are new or have changed since `previous`,
+// and the IDs of the entries of `previous` which are no longer on `board`.
+pub fn diff_boards(
+    previous: &[LeaderboardLiveEntry],
+    board: &[LeaderboardLiveEntry],
+) -> (Vec<LeaderboardLiveEntry>, Vec<i32>) {
+    let entries = board
+        .iter()
+        .filter(|entry| !previous.contains(entry))
+        .cloned()
+        .collect();
+    let removed = previous
+        .iter()
+        .filter(|old| !board.iter().any(|entry| entry.id == old.id))
+        .map(|old| old.id)
+        .collect();
+
+    (entries, removed)
+}
+
+// Fetch the top entries of the difficulty which are pushed to the sockets.
+pub async fn fetch_board(
+    client: &Client,
+    difficulty: &str,
+    config: &Config,
+) -> Result<Vec<LeaderboardLiveEntry>, CustomError> {
+    let mapped_difficulty = constants::DIFFICULTY_MAP
+        .get::<str>(difficulty)
+        .ok_or(CustomError::ValidationError)?;
+
+    let page = LeaderboardPage {
+        limit: config.leaderboard.live_length,
+        offset: 0,
+        after: None,
+        since: None,
+        until: None,
+    };
+
+    let entries =
+        db::get_score_entries(client, &page, *mapped_difficulty, &config.leaderboard).await?;
+
+    Ok(entries
+        .into_iter()
+        .map(LeaderboardLiveEntry::from)
+        .collect())
+}
+
+pub struct LeaderboardSocket {
+    id: usize,
+    heartbeat: Instant,
+    state: AppState,
+}
+
+impl LeaderboardSocket {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            id: 0,
+            heartbeat: Instant::now(),
+            state,
+        }
+    }
+
+    fn send(ctx: &mut ws::WebsocketContext<Self>, update: &LeaderboardLiveUpdate) {
+        if let Ok(text) = serde_json::to_string(update) {
+            ctx.text(text);
+        }
+    }
+
+    fn send_error(ctx: &mut ws::WebsocketContext<Self>, message: &str) {
+        Self::send(
+            ctx,
+            &LeaderboardLiveUpdate::Error {
+                message: message.to_string(),
+            },
+        );
+    }
+
+    fn handle_request(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let difficulties = match serde_json::from_str(text) {
+            Ok(LeaderboardLiveRequest::Subscribe { difficulties }) => difficulties,
+            Ok(LeaderboardLiveRequest::Unsubscribe { difficulties }) => {
+                self.state
+                    .leaderboard_hub
+                    .unsubscribe(self.id, &difficulties);
+                return;
+            }
+            Err(_err) => return Self::send_error(ctx, "Invalid request"),
+        };
+
+        if difficulties
+            .iter()
+            .any(|difficulty| !constants::DIFFICULTY_MAP.contains_key(difficulty.as_str()))
+        {
+            return Self::send_error(ctx, "Invalid difficulty");
+        }
+
+        self.state.leaderboard_hub.subscribe(self.id, &difficulties);
+
+        // Send a snapshot of each difficulty once it has been fetched. The fetched
+        // top entries are published first, so that the other subscribers also
+        // catch up with any changes not caused by a submission (e.g., moderation).
+        let state = self.state.clone();
+        let snapshots = async move {
+            let client = state
+                .pool
+                .get()
+                .await
+                .map_err(|_err| CustomError::DbError)?;
+
+            let mut snapshots = vec![];
+            for difficulty in difficulties {
+                let board = state
+                    .leaderboard_hub
+                    .refresh(&client, &difficulty, &state.config)
+                    .await?;
+                snapshots.push(LeaderboardLiveUpdate::Snapshot {
+                    difficulty,
+                    entries: board,
+                });
+            }
+
+            Ok::<_, CustomError>(snapshots)
+        };
+
+        ctx.spawn(
+            snapshots
+                .into_actor(self)
+                .map(|result, act, ctx| match result {
+                    Ok(snapshots) => {
+                        for snapshot in snapshots.iter() {
+                            Self::send(ctx, snapshot);
+                        }
+                    }
+                    Err(_err) => {
+                        warn!(
+                            act.state.log,
+                            "Error fetching the live leaderboard snapshot"
+                        );
+                        Self::send_error(ctx, "Error fetching the leaderboard");
+                    }
+                }),
+        );
+    }
+}
+
+impl Actor for LeaderboardSocket {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.id = self
+            .state
+            .leaderboard_hub
+            .connect(ctx.address().recipient());
+
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.heartbeat) > CLIENT_TIMEOUT {
+                ctx.stop();
+            } else {
+                ctx.ping(b"");
+            }
+        });
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.state.leaderboard_hub.disconnect(self.id);
+    }
+}
+
+impl Handler<Push> for LeaderboardSocket {
+    type Result = ();
+
+    fn handle(&mut self, push: Push, ctx: &mut Self::Context) {
+        ctx.text(push.0);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for LeaderboardSocket {
+    fn handle(&mut self, message: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match message {
+            Ok(ws::Message::Ping(message)) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&message);
+            }
+            Ok(ws::Message::Pong(_)) => self.heartbeat = Instant::now(),
+            Ok(ws::Message::Text(text)) => self.handle_request(&text, ctx),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => (),
+            Err(_err) => ctx.stop(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MaterialHubState {
+    last_event_id: u64,
+    totals: Option<Vec<MaterialEntry>>,
+    subscribers: Vec<UnboundedSender<Bytes>>,
+}
+
+pub struct MaterialHub {
+    // Event IDs are prefixed with the time at which the hub has been created, so
+    // that the IDs sent before a restart cannot be mistaken for newer events.
+    epoch: u128,
+    // Held while fetching the totals until they have been published (or sent to
+    // a new subscriber), so that older totals never replace newer ones.
+    refresh: AsyncMutex<()>,
+    state: Mutex<MaterialHubState>,
+}
+
+impl Default for MaterialHub {
+    fn default() -> Self {
+        Self {
+            epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis())
+                .unwrap_or_default(),
+            refresh: AsyncMutex::new(()),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl MaterialHub {
+    pub fn has_subscribers(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        // Forget the streams which have been closed in the meantime.
+        state
+            .subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+
+        !state.subscribers.is_empty()
+    }
+
+    // Fetch the current material totals and publish them.
+    pub async fn refresh(&self, client: &Client) -> Result<(), CustomError> {
+        let _guard = self.refresh.lock().await;
+
+        let totals = db::get_total_materials(client).await?;
+        self.publish(totals);
+
+        Ok(())
+    }
+
+    // Remember the new material totals and push them (as a new event) to the
+    // subscribers if they have changed.
+    fn publish(&self, totals: Vec<MaterialEntry>) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.totals.as_ref() == Some(&totals) {
+            return;
+        }
+
+        state.last_event_id += 1;
+        let event = material_event(&self.event_id(state.last_event_id), &totals);
+        state.totals = Some(totals);
+
+        // Forget the streams which have been closed in the meantime.
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+
+    // Return the stream of the material events. It starts with the current
+    // totals, unless the client has already received them (i.e., it resumes
+    // from the ID of the latest event and the totals have not changed since).
+    // Since each event holds all of the totals, the older events that the client
+    // might have missed are not needed. Totals which have not been pushed yet
+    // (e.g., changed while nobody was subscribed) are remembered as a new event,
+    // but only sent to the new subscriber.
+    pub async fn subscribe(
+        &self,
+        client: &Client,
+        last_event_id: Option<&str>,
+    ) -> Result<UnboundedReceiver<Bytes>, CustomError> {
+        let _guard = self.refresh.lock().await;
+
+        let totals = db::get_total_materials(client).await?;
+
+        Ok(self.add_subscriber(last_event_id, totals))
+    }
+
+    fn add_subscriber(
+        &self,
+        last_event_id: Option<&str>,
+        totals: Vec<MaterialEntry>,
+    ) -> UnboundedReceiver<Bytes> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut state = self.state.lock().unwrap();
+
+        state
+            .subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+
+        if state.totals.as_ref() != Some(&totals) {
+            state.last_event_id += 1;
+            state.totals = Some(totals);
+        }
+
+        let event_id = self.event_id(state.last_event_id);
+        if let Some(totals) = &state.totals {
+            if last_event_id != Some(event_id.as_str()) {
+                let _ = sender.unbounded_send(material_event(&event_id, totals));
+            }
+        }
+
+        state.subscribers.push(sender);
+
+        receiver
+    }
+
+    fn event_id(&self, id: u64) -> String {
+        format!("{}-{}", self.epoch, id)
+    }
+}
+
+fn material_event(id: &str, totals: &[MaterialEntry]) -> Bytes {
+    let data = serde_json::to_string(totals).unwrap_or_else(|_err| "[]".to_string());
+
+    Bytes::from(format!("id: {}\nevent: materials\ndata: {}\n\n", id, data))
+}
+
+// Interleave the events with heartbeat comments.
+pub fn with_heartbeats(
+    events: UnboundedReceiver<Bytes>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let heartbeats = stream::unfold((), |_| async {
+        delay_for(EVENT_STREAM_HEARTBEAT_INTERVAL).await;
+        Some((Bytes::from_static(b": heartbeat\n\n"), ()))
+    });
+
+    stream::select(events, Box::pin(heartbeats)).map(Ok)
+}
+
+// Define unit tests for the live leaderboard differences and the material events.
+#[cfg(test)]
+mod tests {
+    use super::{diff_boards, MaterialHub};
+    use crate::models::{LeaderboardLiveEntry, MaterialEntry};
+    use actix_web::web::Bytes;
+    use futures::channel::mpsc::UnboundedReceiver;
+
+    fn entry(id: i32, score: i32, rank: i64) -> LeaderboardLiveEntry {
+        LeaderboardLiveEntry {
+            id,
+            name: "JRT".to_string(),
+            gender: "m".to_string(),
+            score,
+            rank,
+        }
+    }
+
+    #[test]
+    fn test_diff_boards() {
+        let previous = vec![entry(1, 300, 1), entry(2, 200, 2), entry(3, 100, 3)];
+        let board = vec![entry(1, 300, 1), entry(4, 250, 2), entry(2, 200, 3)];
+
+        let (entries, removed) = diff_boards(&previous, &board);
+
+        assert_eq!(entries, vec![entry(4, 250, 2), entry(2, 200, 3)]);
+        assert_eq!(removed, vec![3]);
+
+        let (entries, removed) = diff_boards(&board, &board);
+
+        assert!(entries.is_empty() && removed.is_empty());
+    }
+
+    fn totals(quantity: i32) -> Vec<MaterialEntry> {
+        vec![MaterialEntry {
+            name: "solderedPcb".to_string(),
+            quantity,
+        }]
+    }
+
+    // Return the ID of the next material event sent to the stream (if any).
+    fn next_event_id(events: &mut UnboundedReceiver<Bytes>) -> Option<String> {
+        let event = events.try_next().ok()??;
+
+        std::str::from_utf8(&event)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("id: "))
+            .map(str::to_string)
+    }
+
+    #[test]
+    fn test_material_hub_subscribe() {
+        let hub = MaterialHub::default();
+
+        let mut first = hub.add_subscriber(None, totals(1));
+        let first_id = next_event_id(&mut first).expect("missing snapshot");
+
+        assert!(first_id.starts_with(&format!("{}-", hub.epoch)));
+
+        // A new subscriber only catches up itself.
+        let mut second = hub.add_subscriber(None, totals(1));
+
+        assert_eq!(next_event_id(&mut second), Some(first_id.clone()));
+        assert_eq!(next_event_id(&mut first), None);
+
+        // A resuming subscriber does not get the totals it has already received.
+        let mut resumed = hub.add_subscriber(Some(&first_id), totals(1));
+
+        assert_eq!(next_event_id(&mut resumed), None);
+
+        // Unpushed totals are remembered as a new event.
+        let mut changed = hub.add_subscriber(Some(&first_id), totals(2));
+        let changed_id = next_event_id(&mut changed).expect("missing snapshot");
+
+        assert_ne!(changed_id, first_id);
+        assert_eq!(next_event_id(&mut first), None);
+
+        hub.publish(totals(3));
+
+        for events in [&mut first, &mut second, &mut resumed, &mut changed] {
+            assert!(next_event_id(events).is_some());
+        }
+
+        // Closed streams are forgotten.
+        drop((first, second, resumed, changed));
+
+        assert!(!hub.has_subscribers());
+    }
+}
@@ -0,0 +1,171 @@
+// Define the signed game-session tokens which bind score submissions to a play
+// session started via `/start_session`. A token has the format
+// `<difficulty>.<started_at>.<nonce>.<signature>`, where `started_at` is a Unix
+// timestamp (in seconds), `nonce` is a random hexadecimal string and
+// `signature` is the hexadecimal HMAC-SHA256 of the first three parts.
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, rand::rand_bytes, sign::Signer};
+
+use crate::{config::SessionConfig, constants, errors::CustomError, utils::to_hex};
+
+const NONCE_LENGTH: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub struct SessionClaims {
+    pub difficulty: String,
+    pub started_at: i64,
+    pub nonce: String,
+}
+
+fn sign(secret: &str, payload: &str) -> Result<Vec<u8>, CustomError> {
+    let key = PKey::hmac(secret.as_bytes()).map_err(|_err| CustomError::Internal)?;
+    let mut signer =
+        Signer::new(MessageDigest::sha256(), &key).map_err(|_err| CustomError::Internal)?;
+    signer
+        .update(payload.as_bytes())
+        .map_err(|_err| CustomError::Internal)?;
+
+    signer.sign_to_vec().map_err(|_err| CustomError::Internal)
+}
+
+pub fn issue_token(secret: &str, difficulty: &str, started_at: i64) -> Result<String, CustomError> {
+    let mut nonce = [0; NONCE_LENGTH];
+    rand_bytes(&mut nonce).map_err(|_err| CustomError::Internal)?;
+
+    let payload = format!("{}.{}.{}", difficulty, started_at, to_hex(&nonce));
+    let signature = sign(secret, &payload)?;
+
+    Ok(format!("{}.{}", payload, to_hex(&signature)))
+}
+
+// Check the signature of the token and return its claims. Malformed tokens are
+// rejected as validation errors while tokens with invalid signatures are
+// rejected as forbidden.
+pub fn verify_token(secret: &str, token: &str) -> Result<SessionClaims, CustomError> {
+    let (payload, signature) = token.rsplit_once('.').ok_or(CustomError::ValidationError)?;
+
+    let parts: Vec<&str> = payload.split('.').collect();
+    let (difficulty, started_at, nonce) = match parts[..] {
+        [difficulty, started_at, nonce] => (difficulty, started_at, nonce),
+        _ => return Err(CustomError::ValidationError),
+    };
+
+    if !constants::DIFFICULTY_MAP.contains_key(difficulty)
+        || nonce.len() != NONCE_LENGTH * 2
+        || !nonce.bytes().all(|byte| byte.is_ascii_hexdigit())
+    {
+        return Err(CustomError::ValidationError);
+    }
+
+    let started_at: i64 = started_at
+        .parse()
+        .map_err(|_err| CustomError::ValidationError)?;
+
+    let expected = to_hex(&sign(secret, payload)?);
+
+    // Compare in constant time to avoid leaking the expected signature.
+    if signature.len() != expected.len() || !memcmp::eq(signature.as_bytes(), expected.as_bytes()) {
+        return Err(CustomError::Forbidden);
+    }
+
+    Ok(SessionClaims {
+        difficulty: difficulty.to_string(),
+        started_at,
+        nonce: nonce.to_string(),
+    })
+}
+
+// Check that the session has been started for the claimed difficulty, has lasted
+// at least as long as the shortest possible game and has not expired yet.
+// Whether the claimed score could have been achieved within the session is
+// judged by the plausibility model instead (see `plausibility`).
+pub fn check_if_session_valid(
+    claims: &SessionClaims,
+    difficulty: &str,
+    now: i64,
+    config: &SessionConfig,
+) -> bool {
+    let elapsed = now - claims.started_at;
+
+    claims.difficulty == difficulty
+        && elapsed >= config.min_duration as i64
+        && elapsed <= config.lifetime as i64
+}
+
+// Define unit tests for the session tokens.
+#[cfg(test)]
+mod tests {
+    use super::{check_if_session_valid, issue_token, verify_token};
+    use crate::{config::SessionConfig, errors::CustomError};
+
+    const SECRET: &str = "unit-test-session-secret-0123456789";
+
+    #[test]
+    fn test_issued_token_can_be_verified() {
+        let token = issue_token(SECRET, "normal", 1_600_000_000).expect("error issuing token");
+        let claims = verify_token(SECRET, &token).expect("error verifying token");
+
+        assert_eq!(claims.difficulty, "normal".to_string());
+        assert_eq!(claims.started_at, 1_600_000_000);
+        assert_eq!(claims.nonce.len(), 32);
+    }
+
+    #[test]
+    fn test_tampered_token_is_rejected() {
+        let token = issue_token(SECRET, "easy", 1_600_000_000).expect("error issuing token");
+
+        match verify_token(SECRET, &token.replacen("easy", "hard", 1)) {
+            Err(CustomError::Forbidden) => (),
+            _ => panic!("tampered token should be rejected as forbidden"),
+        }
+        match verify_token("another-secret-of-at-least-32-bytes", &token) {
+            Err(CustomError::Forbidden) => (),
+            _ => panic!("token signed with another secret should be rejected as forbidden"),
+        }
+        match verify_token(SECRET, "not-a-token") {
+            Err(CustomError::ValidationError) => (),
+            _ => panic!("malformed token should be rejected as a validation error"),
+        }
+    }
+
+    #[test]
+    fn test_session_validity() {
+        let config = SessionConfig {
+            secret: Some(SECRET.to_string()),
+            lifetime: 3600,
+            min_duration: 10,
+        };
+        let token = issue_token(SECRET, "hard", 1_600_000_000).expect("error issuing token");
+        let claims = verify_token(SECRET, &token).expect("error verifying token");
+
+        assert!(check_if_session_valid(
+            &claims,
+            "hard",
+            1_600_000_100,
+            &config
+        ));
+        assert!(!check_if_session_valid(
+            &claims,
+            "hard",
+            1_599_999_999,
+            &config
+        ));
+        assert!(!check_if_session_valid(
+            &claims,
+            "hard",
+            1_600_000_001,
+            &config
+        ));
+        assert!(!check_if_session_valid(
+            &claims,
+            "easy",
+            1_600_000_100,
+            &config
+        ));
+        assert!(!check_if_session_valid(
+            &claims,
+            "hard",
+            1_600_003_601,
+            &config
+        ));
+    }
+}
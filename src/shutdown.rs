@@ -0,0 +1,51 @@
+// Define graceful shutdown handling. The servers are started with their own
+// signal handling disabled so that both listeners are stopped together (and so
+// that the database pool can be closed after the last request has finished).
+use actix_rt::signal::unix::{signal, SignalKind};
+use actix_web::dev::Server;
+use deadpool_postgres::{Client, Pool};
+use futures::future::{join_all, select, Either};
+use slog::{info, o, Logger};
+
+use crate::errors::StartupError;
+
+// Stop accepting new connections once SIGTERM (sent by `systemctl stop` and
+// `systemctl restart`) or SIGINT is received, and wait for the in-flight
+// requests to finish (up to the servers' shutdown timeout).
+pub fn spawn_signal_handler(servers: Vec<Server>, log: Logger) -> Result<(), StartupError> {
+    let log = log.new(o!("task" => "shutdown"));
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    actix_rt::spawn(async move {
+        let received = match select(Box::pin(terminate.recv()), Box::pin(interrupt.recv())).await
+        {
+            Either::Left(_) => "SIGTERM",
+            Either::Right(_) => "SIGINT",
+        };
+
+        info!(log, "Stopping server gracefully"; "signal" => received);
+
+        join_all(servers.iter().map(|server| server.stop(true))).await;
+    });
+
+    Ok(())
+}
+
+// Close all idle database connections. Deadpool cannot be closed explicitly, so
+// the connections are taken out of the pool and dropped one by one instead.
+pub async fn close_pool(pool: &Pool) -> usize {
+    let mut closed = 0;
+
+    while pool.status().available > 0 {
+        match pool.get().await {
+            Ok(client) => {
+                drop(Client::take(client));
+                closed += 1;
+            }
+            Err(_err) => break,
+        }
+    }
+
+    closed
+}
@@ -0,0 +1,265 @@
+// Define TLS acceptor setup and certificate hot-reloading for the HTTPS
+// listener.
+use actix_rt::{
+    signal::unix::{signal, SignalKind},
+    time::interval,
+};
+use openssl::{
+    error::ErrorStack,
+    ssl::{
+        SniError, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype, SslMethod,
+        SslVerifyMode,
+    },
+    x509::X509Name,
+};
+use slog::{error, info, o, Logger};
+use std::{
+    fs,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::{config::TlsConfig, errors::StartupError};
+
+// Attach the offending file path to OpenSSL errors so that misconfigured paths
+// are easy to spot in the startup error message.
+fn tls_file_error(path: &str) -> impl Fn(ErrorStack) -> StartupError + '_ {
+    move |source| StartupError::TlsFile {
+        path: path.to_string(),
+        source,
+    }
+}
+
+fn build_acceptor(config: &TlsConfig) -> Result<SslAcceptorBuilder, StartupError> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+
+    builder
+        .set_private_key_file(&config.key, SslFiletype::PEM)
+        .map_err(tls_file_error(&config.key))?;
+    builder
+        .set_certificate_chain_file(&config.cert_chain)
+        .map_err(tls_file_error(&config.cert_chain))?;
+    builder.check_private_key()?;
+
+    // Only request (and require) client certificates if a client CA is configured.
+    if let Some(client_ca) = &config.client_ca {
+        builder
+            .set_ca_file(client_ca)
+            .map_err(tls_file_error(client_ca))?;
+        builder.set_client_ca_list(
+            X509Name::load_client_ca_file(client_ca).map_err(tls_file_error(client_ca))?,
+        );
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    builder.set_min_proto_version(Some(config.min_version.into()))?;
+
+    if let Some(ciphers) = &config.ciphers {
+        builder.set_cipher_list(ciphers)?;
+    }
+
+    Ok(builder)
+}
+
+// Holds the most recently loaded server context. The acceptor handed to the
+// HTTP server swaps every new handshake over to this context, so replacing it
+// only affects new connections while existing connections keep their
+// original certificate.
+#[derive(Clone)]
+pub struct CertificateStore {
+    config: TlsConfig,
+    context: Arc<RwLock<SslContext>>,
+}
+
+impl CertificateStore {
+    pub fn new(config: &TlsConfig) -> Result<Self, StartupError> {
+        let context = build_acceptor(config)?.build().into_context();
+
+        Ok(Self {
+            config: config.clone(),
+            context: Arc::new(RwLock::new(context)),
+        })
+    }
+
+    pub fn configure_acceptor(&self) -> Result<SslAcceptorBuilder, StartupError> {
+        let mut builder = build_acceptor(&self.config)?;
+        let context = self.context.clone();
+
+        // The servername callback is invoked for every ClientHello (even without
+        // SNI), which makes it the earliest point at which we can swap contexts.
+        builder.set_servername_callback(move |ssl, _alert| {
+            let context = context.read().unwrap_or_else(PoisonError::into_inner);
+            ssl.set_ssl_context(&context)
+                .map_err(|_err| SniError::ALERT_FATAL)
+        });
+
+        Ok(builder)
+    }
+
+    // Re-read the certificate chain and private key from disk. The previously
+    // loaded context is kept if the new files cannot be loaded.
+    pub fn reload(&self) -> Result<(), StartupError> {
+        let context = build_acceptor(&self.config)?.build().into_context();
+
+        // A writer can only poison the lock by panicking while swapping the
+        // context, which leaves a fully built context behind, so it is safe to
+        // keep using the lock afterwards.
+        *self.context.write().unwrap_or_else(PoisonError::into_inner) = context;
+
+        Ok(())
+    }
+
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        [&self.config.cert_chain, &self.config.key]
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn reload_and_log(&self, log: &Logger, trigger: &str) -> bool {
+        match self.reload() {
+            Ok(_) => {
+                info!(log, "Reloaded TLS certificate"; "trigger" => trigger);
+                true
+            }
+            Err(err) => {
+                let sublog = log.new(o!("cause" => err.to_string()));
+                error!(sublog, "Error reloading TLS certificate"; "trigger" => trigger);
+                false
+            }
+        }
+    }
+}
+
+// Reload the certificates whenever SIGHUP is received and, if `poll_interval`
+// is non-zero, whenever the modification time of the configured files changes.
+pub fn spawn_reloader(store: CertificateStore, poll_interval: u64, log: Logger) {
+    let log = log.new(o!("task" => "tls_reloader"));
+
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangups) => {
+            let store = store.clone();
+            let log = log.clone();
+            actix_rt::spawn(async move {
+                while hangups.recv().await.is_some() {
+                    store.reload_and_log(&log, "SIGHUP");
+                }
+            });
+        }
+        Err(err) => {
+            let sublog = log.new(o!("cause" => err.to_string()));
+            error!(sublog, "Error registering SIGHUP handler");
+        }
+    }
+
+    if poll_interval > 0 {
+        actix_rt::spawn(async move {
+            let mut last_modified = store.modified_times();
+            let mut ticks = interval(Duration::from_secs(poll_interval));
+
+            loop {
+                ticks.tick().await;
+
+                let modified = store.modified_times();
+
+                // Only remember the new modification times once the reload succeeds
+                // so that a half-written certificate is retried on the next tick.
+                if modified != last_modified && store.reload_and_log(&log, "file change") {
+                    last_modified = modified;
+                }
+            }
+        });
+    }
+}
+
+// Define unit tests for the TLS acceptor setup.
+#[cfg(test)]
+mod tests {
+    use super::CertificateStore;
+    use crate::{config::TlsConfig, errors::StartupError};
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{X509Name, X509},
+    };
+    use std::{fs, path::PathBuf, sync::PoisonError};
+
+    // Write a freshly generated self-signed certificate and its private key to
+    // the given paths.
+    fn write_self_signed_certificate(common_name: &str, cert_chain: &PathBuf, key: &PathBuf) {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+
+        fs::write(cert_chain, builder.build().to_pem().unwrap()).unwrap();
+        fs::write(key, pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    }
+
+    fn served_common_name(store: &CertificateStore) -> String {
+        let context = store.context.read().unwrap_or_else(PoisonError::into_inner);
+        let certificate = context.certificate().expect("context has no certificate");
+        let entry = certificate.subject_name().entries().next().unwrap();
+
+        entry.data().as_utf8().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_missing_private_key_file() {
+        let config = TlsConfig {
+            key: "tls/does_not_exist.pem".to_string(),
+            ..TlsConfig::default()
+        };
+
+        match CertificateStore::new(&config) {
+            Err(StartupError::TlsFile { path, .. }) => {
+                assert_eq!(path, "tls/does_not_exist.pem".to_string())
+            }
+            _ => panic!("missing private key file should return a TLS file error"),
+        }
+    }
+
+    #[test]
+    fn test_reload_swaps_served_context() {
+        let dir = std::env::temp_dir().join(format!("tls-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cert_chain = dir.join("fullchain.pem");
+        let key = dir.join("privkey.pem");
+
+        write_self_signed_certificate("certificate-a", &cert_chain, &key);
+
+        let config = TlsConfig {
+            cert_chain: cert_chain.to_str().unwrap().to_string(),
+            key: key.to_str().unwrap().to_string(),
+            ..TlsConfig::default()
+        };
+        let store = CertificateStore::new(&config).expect("error loading certificate A");
+        assert_eq!(served_common_name(&store), "certificate-a");
+
+        write_self_signed_certificate("certificate-b", &cert_chain, &key);
+        store.reload().expect("error reloading certificate B");
+        assert_eq!(served_common_name(&store), "certificate-b");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
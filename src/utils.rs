@@ -1,9 +1,14 @@
-use deadpool_postgres::Client;
+use chrono::{DateTime, TimeZone, Utc};
+use deadpool_postgres::{Client, Transaction};
+use openssl::{base64, sha::sha256};
+use serde::Serialize;
+use std::io::Write;
 use tokio_pg_mapper::FromTokioPostgresRow;
 
 use crate::{
+    config::ScoringConfig,
     errors::CustomError,
-    models::{MaterialEntry, MaterialValueEntry},
+    models::{GoalMaterialProgress, MaterialEntry, MaterialValueEntry, ScoreEntry},
 };
 
 pub async fn get_values_of_materials(
@@ -25,6 +30,22 @@ pub async fn get_values_of_materials(
     Ok(result)
 }
 
+// Check that the submitted score, bonus and material quantities lie within the
+// limits configured for the current event.
+pub fn check_if_entry_within_limits(item: &ScoreEntry, limits: &ScoringConfig) -> bool {
+    let within = |value: i32, min: i32, max: i32| (min..=max).contains(&value);
+
+    within(item.score, limits.min_score, limits.max_score)
+        && within(item.bonus, limits.min_bonus_value, limits.max_bonus_value)
+        && item.materials.iter().all(|material| {
+            within(
+                material.quantity,
+                limits.min_materials,
+                limits.max_materials,
+            )
+        })
+}
+
 pub async fn check_if_materials_total_value_sum_up_to_score(
     score: i32,
     materials: Vec<MaterialEntry>,
@@ -34,7 +55,7 @@ pub async fn check_if_materials_total_value_sum_up_to_score(
     let mut sum: i32 = 0;
 
     // Ensure that materials array is not strangely constructed/parsed.
-    if (materials.is_empty() && materials.len() > 0) || (materials.capacity() < materials.len()) {
+    if materials.capacity() < materials.len() {
         return Ok(false);
     }
 
@@ -46,7 +67,7 @@ pub async fn check_if_materials_total_value_sum_up_to_score(
     // Check that materials vector does not contain any materials not specified in
     // the currently-existing material names in the database.
     for material in materials.iter() {
-        if !(&values).iter().any(|value| value.name == material.name) {
+        if !values.iter().any(|value| value.name == material.name) {
             return Ok(false);
         }
     }
@@ -91,7 +112,7 @@ pub async fn check_if_materials_total_value_sum_up_to_score(
 // (execution time is much faster for a single bigger nested query compared to
 // multiple smaller queries).
 pub async fn add_materials_to_aggregate(
-    client: &Client,
+    client: &Transaction<'_>,
     materials: Vec<MaterialEntry>,
 ) -> Result<bool, CustomError> {
     // Initialize mutable SQL statement to be used for database update (variable name courtesy of Filbert - https://github.com/FolkLoreee).
@@ -141,11 +162,128 @@ pub async fn add_materials_to_aggregate(
     Ok(result)
 }
 
+// Idempotency keys are usually UUIDs, but any reasonably short string of
+// visible ASCII characters is accepted.
+pub fn check_if_idempotency_key_valid(key: &str) -> bool {
+    (1..=255).contains(&key.len()) && key.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+// Remove the port (if any) from a `Host` header value, taking bracketed IPv6
+// addresses into account.
+pub fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        };
+    }
+
+    match host.rsplit_once(':') {
+        Some((name, _port)) => name,
+        None => host,
+    }
+}
+
+// Write the records as CSV, with a header row derived from the field names.
+pub fn write_csv<T: Serialize>(writer: impl Write, records: &[T]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for record in records.iter() {
+        writer.serialize(record)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Encode the score, submission time (in microseconds) and ID of the last entry of
+// a leaderboard page as an opaque (URL-safe) cursor. Unlike the rank, these do
+// not change when other entries are submitted or deleted.
+pub fn encode_cursor(score: i32, creation_date: DateTime<Utc>, id: i32) -> String {
+    let micros =
+        creation_date.timestamp() * 1_000_000 + i64::from(creation_date.timestamp_subsec_micros());
+
+    base64::encode_block(format!("{}.{}.{}", score, micros, id).as_bytes())
+        .replace('+', "-")
+        .replace('/', "_")
+        .trim_end_matches('=')
+        .to_string()
+}
+
+pub fn decode_cursor(cursor: &str) -> Option<(i32, DateTime<Utc>, i32)> {
+    let mut encoded = cursor.replace('-', "+").replace('_', "/");
+    encoded.push_str(&"=".repeat((4 - encoded.len() % 4) % 4));
+
+    let decoded = base64::decode_block(&encoded).ok()?;
+    let parts: Vec<&str> = std::str::from_utf8(&decoded).ok()?.split('.').collect();
+
+    let (score, micros, id) = match parts[..] {
+        [score, micros, id] => (
+            score.parse::<i32>().ok()?,
+            micros.parse::<i64>().ok()?,
+            id.parse::<i32>().ok()?,
+        ),
+        _ => return None,
+    };
+
+    let creation_date = Utc
+        .timestamp_opt(
+            micros.div_euclid(1_000_000),
+            (micros.rem_euclid(1_000_000) * 1000) as u32,
+        )
+        .single()?;
+
+    if score < 0 {
+        return None;
+    }
+
+    Some((score, creation_date, id))
+}
+
+// Calculate the percentage of the required materials which have been collected,
+// rounded to 2 decimal places (surplus quantities of a material do not count).
+pub fn calculate_goal_progress(materials: &[GoalMaterialProgress]) -> f64 {
+    let required: i64 = materials
+        .iter()
+        .map(|material| i64::from(material.required))
+        .sum();
+    let collected: i64 = materials
+        .iter()
+        .map(|material| i64::from(material.quantity.clamp(0, material.required)))
+        .sum();
+
+    if required == 0 {
+        return 0.0;
+    }
+
+    (collected as f64 * 10000.0 / required as f64).floor() / 100.0
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Digest of a score submission, which is stored together with its idempotency
+// key so that a reused key can be told apart from a genuine retry.
+pub fn hash_score_entry(item: &ScoreEntry) -> String {
+    to_hex(&sha256(
+        &serde_json::to_vec(item).expect("error serializing score entry"),
+    ))
+}
+
 // Define unit tests for the payload validation logic.
 #[cfg(test)]
 mod tests {
-    use super::check_if_materials_total_value_sum_up_to_score;
-    use crate::models::{MaterialEntry, MaterialValueEntry};
+    use super::{
+        calculate_goal_progress, check_if_entry_within_limits, check_if_idempotency_key_valid,
+        check_if_materials_total_value_sum_up_to_score, decode_cursor, encode_cursor, strip_port,
+    };
+    use crate::{
+        config::ScoringConfig,
+        models::{GoalMaterialProgress, MaterialEntry, MaterialValueEntry, ScoreEntry},
+    };
+    use chrono::{Duration, TimeZone, Utc};
 
     // Define macro to await async function to return result.
     macro_rules! aw {
@@ -166,7 +304,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, true);
+        assert!(result);
     }
 
     #[test]
@@ -181,7 +319,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, false);
+        assert!(!result);
     }
 
     #[test]
@@ -196,7 +334,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, false);
+        assert!(!result);
     }
 
     #[test]
@@ -217,7 +355,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, false);
+        assert!(!result);
     }
 
     #[test]
@@ -250,7 +388,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, false);
+        assert!(!result);
     }
 
     #[test]
@@ -277,7 +415,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, false);
+        assert!(!result);
     }
 
     #[test]
@@ -314,7 +452,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, false);
+        assert!(!result);
     }
 
     #[test]
@@ -351,7 +489,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, false);
+        assert!(!result);
     }
 
     #[test]
@@ -388,7 +526,7 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, true);
+        assert!(result);
     }
 
     #[test]
@@ -425,6 +563,126 @@ mod tests {
         ))
         .expect("error running score validator function");
 
-        assert_eq!(result, true);
+        assert!(result);
+    }
+
+    #[test]
+    fn test_strip_port_from_host() {
+        assert_eq!(strip_port("sutdoverdue.dev"), "sutdoverdue.dev");
+        assert_eq!(strip_port("sutdoverdue.dev:80"), "sutdoverdue.dev");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn test_entry_within_default_limits() {
+        let item = ScoreEntry {
+            name: "JRT".to_string(),
+            gender: "m".to_string(),
+            email: "james_raphael@mymail.sutd.edu.sg".to_string(),
+            difficulty: "easy".to_string(),
+            score: 4060,
+            bonus: 10,
+            materials: vec![MaterialEntry {
+                name: "portalGun".to_string(),
+                quantity: 10,
+            }],
+            session_token: None,
+        };
+
+        assert!(check_if_entry_within_limits(
+            &item,
+            &ScoringConfig::default()
+        ));
+    }
+
+    #[test]
+    fn test_entry_exceeding_configured_limits() {
+        let limits = ScoringConfig {
+            max_score: 1000,
+            max_materials: 5,
+            ..ScoringConfig::default()
+        };
+
+        let item = ScoreEntry {
+            name: "JRT".to_string(),
+            gender: "m".to_string(),
+            email: "james_raphael@mymail.sutd.edu.sg".to_string(),
+            difficulty: "easy".to_string(),
+            score: 60,
+            bonus: 0,
+            materials: vec![MaterialEntry {
+                name: "portalGun".to_string(),
+                quantity: 6,
+            }],
+            session_token: None,
+        };
+
+        assert!(!check_if_entry_within_limits(&item, &limits));
+
+        let item = ScoreEntry {
+            score: 1001,
+            materials: vec![],
+            ..item
+        };
+
+        assert!(!check_if_entry_within_limits(&item, &limits));
+    }
+
+    #[test]
+    fn test_idempotency_key_format() {
+        assert!(check_if_idempotency_key_valid(
+            "3f1c2a9e-8a4b-4d5e-9c6f-0b1a2c3d4e5f"
+        ));
+        assert!(!check_if_idempotency_key_valid(""));
+        assert!(!check_if_idempotency_key_valid("retry 1"));
+        assert!(!check_if_idempotency_key_valid(&"k".repeat(256)));
+    }
+
+    #[test]
+    fn test_leaderboard_cursor_round_trip() {
+        for (score, micros, id) in [
+            (0, 0, 1),
+            (1500, 1_611_547_200_123_456, 2048),
+            (i32::MAX, -1, i32::MAX),
+        ] {
+            let creation_date = Utc.timestamp(0, 0) + Duration::microseconds(micros);
+            let cursor = encode_cursor(score, creation_date, id);
+
+            assert!(cursor
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'));
+            assert_eq!(decode_cursor(&cursor), Some((score, creation_date, id)));
+        }
+
+        assert_eq!(decode_cursor(""), None);
+        assert_eq!(decode_cursor("not a cursor"), None);
+        assert_eq!(
+            decode_cursor(&encode_cursor(-1, Utc.timestamp(0, 0), 5)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_calculate_goal_progress() {
+        let material = |quantity, required| GoalMaterialProgress {
+            name: "solderedPcb".to_string(),
+            quantity,
+            required,
+        };
+
+        assert_eq!(calculate_goal_progress(&[material(0, 10)]), 0.0);
+        assert_eq!(
+            calculate_goal_progress(&[material(50, 100), material(0, 200)]),
+            16.66
+        );
+        assert_eq!(
+            calculate_goal_progress(&[material(500, 100), material(100, 200)]),
+            66.66
+        );
+        assert_eq!(
+            calculate_goal_progress(&[material(100, 100), material(250, 200)]),
+            100.0
+        );
     }
 }